@@ -75,7 +75,8 @@ fn main() {
                         .text(format!("{}\n", pangrams[pangram_idx]))
                         .text(format!("!\"#$%&'()*+,-./\n"))
                         .text(format!("1234567890:;<=>?\n"))
-                        .text(format!("[\\]^_`{{|}}~\n"));
+                        .text(format!("[\\]^_`{{|}}~\n"))
+                        .markup("{red}Marked {em}up{/} text{/} with {yellow}colors{/}\n");
                     fonter.draw(V2(0.0, 0.0));
                 }
 
@@ -1,27 +1,25 @@
 use time;
 use std::mem;
+use std::hash::Hash;
+use std::collections::{HashMap, HashSet};
 use std::time::duration::Duration;
 use std::thread;
 use std::default::Default;
+use std::char;
 use image::{GenericImage, SubImage, Pixel};
 use image::{ImageBuffer, Rgba};
 use image;
 use glutin;
 use glium::{self, DisplayBuild};
-use util::{self, AtlasBuilder, Atlas, AtlasItem, V2, Rgb, Color};
+use util::{self, AtlasBuilder, Atlas, AtlasItem, V2, Rect, Rgb, Color};
+use util::tiled::Tileset;
+use util::bmfont::BmFont;
+use util::locale::Catalog;
 use event::{Event, MouseButton};
 use renderer::{Renderer, Vertex};
+use font::{FontData, Glyph, GlyphImage};
 use scancode;
-use ::{WidgetId};
-
-/// Width of the full font cell. Actual variable-width letters occupy some
-/// portion of the left side of the cell.
-pub static FONT_W: u32 = 8;
-/// Height of the font.
-pub static FONT_H: u32 = 8;
-
-/// The first font image in the atlas image set.
-static FONT_IDX: usize = 0;
+use ::{WidgetId, IdStack};
 
 /// Image index of the solid color texture block.
 static SOLID_IDX: usize = 96;
@@ -31,6 +29,8 @@ pub struct CanvasBuilder {
     size: V2<u32>,
     frame_interval: Option<f64>,
     builder: AtlasBuilder,
+    fonts: Vec<FontData>,
+    icons: HashMap<String, Image>,
 }
 
 /// Toplevel graphics drawing and input reading context.
@@ -41,6 +41,8 @@ impl CanvasBuilder {
             size: V2(640, 360),
             frame_interval: None,
             builder: AtlasBuilder::new(),
+            fonts: Vec::new(),
+            icons: HashMap::new(),
         };
         ret.init_font();
         ret.init_solid();
@@ -72,13 +74,97 @@ impl CanvasBuilder {
         Image(self.builder.push(offset, image))
     }
 
+    /// Add the tiles of a Tiled tileset into the canvas image atlas. The
+    /// image is the tileset image file. Returns the tile images in tileset
+    /// order. Image collection tilesets need their tile images added with
+    /// add_image instead.
+    pub fn add_tileset<P: Pixel<Subpixel=u8> + 'static, I: GenericImage<Pixel=P>>(
+        &mut self, tileset: &Tileset, image: &mut I) -> Vec<Image> {
+        tileset.push_tiles(&mut self.builder, image).into_iter().map(|i| Image(i)).collect()
+    }
+
+    /// Create a new empty font with the given line height and distance from
+    /// the top of the line to the baseline. Populate the font with add_glyph.
+    pub fn new_font(&mut self, height: f32, ascent: f32) -> Font {
+        self.fonts.push(FontData::new(height, ascent));
+        Font(self.fonts.len() - 1)
+    }
+
+    /// Add a glyph image for a char into a font. Offset is the draw offset of
+    /// the image relative to the pen position on the baseline. If advance is
+    /// None, it is inferred from the width of the image.
+    pub fn add_glyph<P: Pixel<Subpixel=u8> + 'static, I: GenericImage<Pixel=P>>(
+        &mut self, Font(idx): Font, c: char, offset: V2<i32>, advance: Option<f32>, image: &I) {
+        let image = self.add_image(offset, image);
+        self.fonts[idx].insert_glyph(c, Glyph { image: image, advance: advance });
+    }
+
+    /// Set the kerning adjustment for a pair of chars in a font.
+    pub fn add_kerning(&mut self, Font(idx): Font, left: char, right: char, amount: f32) {
+        self.fonts[idx].insert_kerning(left, right, amount);
+    }
+
+    /// Set a font to look up the chars missing from another font from, or
+    /// None for no fallback font. Fallback fonts can have fallback fonts of
+    /// their own to form a chain.
+    pub fn set_fallback_font(&mut self, Font(idx): Font, fallback: Option<Font>) {
+        self.fonts[idx].fallback_font = fallback;
+    }
+
+    /// Set the char that is drawn in place of chars that are missing from a
+    /// font and all its fallback fonts. Fonts, including the built-in
+    /// default font, have no fallback glyph by default and missing chars
+    /// are not drawn at all. Set eg. '?' to make them show up.
+    pub fn set_fallback_glyph(&mut self, Font(idx): Font, c: Option<char>) {
+        self.fonts[idx].fallback_glyph = c;
+    }
+
+    /// Reserve free space in the texture atlas for glyphs loaded from dynamic
+    /// glyph sources while the canvas is running.
+    pub fn reserve_atlas_area(&mut self, area: u32) {
+        self.builder.reserve(area as i32);
+    }
+
+    /// Load a font in the text format of the BMFont tool. Pages are the
+    /// texture page images of the font in the order of the page ids of the
+    /// descriptor.
+    pub fn add_bmfont<P: Pixel<Subpixel=u8> + 'static, I: GenericImage<Pixel=P>>(
+        &mut self, descriptor: &str, pages: &mut [I]) -> Result<Font, String> {
+        let desc = try!(BmFont::parse(descriptor));
+        if let Some(ch) = desc.chars.iter().find(|ch| ch.page >= pages.len()) {
+            return Err(format!("Missing texture page {} for char {:?}", ch.page, ch.id));
+        }
+
+        let font = self.new_font(desc.line_height as f32, desc.base as f32);
+        for ch in desc.chars.iter() {
+            let Rect(pos, dim) = ch.rect;
+            self.add_glyph(
+                font, ch.id, ch.offset - V2(0, desc.base), Some(ch.advance as f32),
+                &SubImage::new(&mut pages[ch.page],
+                               pos.0 as u32, pos.1 as u32, dim.0 as u32, dim.1 as u32));
+        }
+        for &(left, right, amount) in desc.kernings.iter() {
+            self.add_kerning(font, left, right, amount as f32);
+        }
+        Ok(font)
+    }
+
+    /// Name an image as an icon that can be embedded in Fonter markup text
+    /// with `{icon:name}`. The icon image is positioned relative to the
+    /// baseline of the text like font glyphs are.
+    pub fn set_icon(&mut self, name: &str, img: Image) {
+        self.icons.insert(name.to_string(), img);
+    }
+
     /// Start running the engine, return an event iteration.
     pub fn run(&mut self) -> Canvas {
         Canvas::new(
             self.size,
             &self.title[..],
             self.frame_interval,
-            Atlas::new(&self.builder))
+            Atlas::new(&self.builder),
+            self.fonts.clone(),
+            self.icons.clone())
     }
 
     /// Load the default font into the texture atlas.
@@ -86,13 +172,17 @@ impl CanvasBuilder {
         let mut font_sheet = util::color_key(
             &image::load_from_memory(include_bytes!("../assets/font.png")).unwrap(),
             &Rgb::new(0x80u8, 0x80u8, 0x80u8));
+        let font = self.new_font(8.0, 8.0);
+        assert!(font == Default::default());
         for i in 0u32..96 {
             let x = 8u32 * (i % 16u32);
             let y = 8u32 * (i / 16u32);
-            let Image(idx) = self.add_image(
-                V2(0, -8),
+            let c = char::from_u32(32 + i).unwrap();
+            // Special case for space, the atlas image won't have a width.
+            let advance = if c == ' ' { Some(4.0) } else { None };
+            self.add_glyph(
+                font, c, V2(0, -8), advance,
                 &SubImage::new(&mut font_sheet, x, y, 8, 8));
-            assert!(idx - i as usize == FONT_IDX);
         }
     }
 
@@ -111,6 +201,16 @@ pub struct Canvas {
     renderer: Renderer,
 
     atlas: Atlas,
+    /// Whether images have been added to the atlas since the atlas texture
+    /// was last uploaded.
+    atlas_dirty: bool,
+    fonts: Vec<FontData>,
+    icons: HashMap<String, Image>,
+    glyph_sources: HashMap<Font, Box<Fn(char) -> Option<GlyphImage>>>,
+    /// Chars that no glyph source could provide. Cleared when the font
+    /// configuration changes.
+    missing_glyphs: HashSet<(Font, char)>,
+    catalog: Catalog,
 
     state: State,
     frame_interval: Option<f64>,
@@ -126,12 +226,16 @@ pub struct Canvas {
 
     pub mouse_pos: V2<f32>,
     pub mouse_pressed: bool,
-    /// Imgui widget currently under mouse cursor.
+    /// Imgui widget currently under mouse cursor. The ID has the ID
+    /// scopes applied, compare with is_hot.
     pub hot_widget: Option<WidgetId>,
-    /// Imgui widget currently being interacted with.
+    /// Imgui widget currently being interacted with. The ID has the ID
+    /// scopes applied, compare with is_active.
     pub active_widget: Option<WidgetId>,
     /// Previous imgui widget.
     pub last_widget: Option<WidgetId>,
+    /// Stack of imgui ID scopes.
+    id_stack: IdStack,
 }
 
 #[derive(PartialEq)]
@@ -145,7 +249,9 @@ impl Canvas {
         size: V2<u32>,
         title: &str,
         frame_interval: Option<f64>,
-        atlas: Atlas) -> Canvas {
+        atlas: Atlas,
+        fonts: Vec<FontData>,
+        icons: HashMap<String, Image>) -> Canvas {
 
         let display = glutin::WindowBuilder::new()
             .with_title(title.to_string())
@@ -163,6 +269,12 @@ impl Canvas {
             renderer: renderer,
 
             atlas: atlas,
+            atlas_dirty: false,
+            fonts: fonts,
+            icons: icons,
+            glyph_sources: HashMap::new(),
+            missing_glyphs: HashSet::new(),
+            catalog: Catalog::new("en"),
 
             state: State::Normal,
             frame_interval: frame_interval,
@@ -180,6 +292,7 @@ impl Canvas {
             hot_widget: None,
             active_widget: None,
             last_widget: None,
+            id_stack: IdStack::default(),
         }
     }
 
@@ -224,15 +337,117 @@ impl Canvas {
 
     /// Return the image corresponding to a char in the built-in font.
     pub fn font_image(&self, c: char) -> Option<Image> {
-        let idx = c as usize;
-        // Hardcoded limiting of the font to printable ASCII.
-        if idx >= 32 && idx < 128 {
-            Some(Image((idx - 32) as usize + FONT_IDX))
-        } else {
-            None
+        self.glyph(Default::default(), c).map(|g| g.image)
+    }
+
+    /// Return a named icon image.
+    pub fn icon(&self, name: &str) -> Option<Image> {
+        self.icons.get(name).map(|&x| x)
+    }
+
+    /// Return the metrics of a font.
+    pub fn font_data<'a>(&'a self, Font(idx): Font) -> &'a FontData {
+        &self.fonts[idx]
+    }
+
+    /// Return the glyph for a char in a font. Chars missing from the font
+    /// are looked up from its fallback fonts, and the fallback glyph of the
+    /// font is used if none of them have the char.
+    pub fn glyph(&self, font: Font, c: char) -> Option<Glyph> {
+        self.find_glyph(font, c).or_else(
+            || self.font_data(font).fallback_glyph.and_then(|fc| self.find_glyph(font, fc)))
+    }
+
+    /// Set the string catalog that Fonter translates text with. Text is laid
+    /// out anew every frame, so the new language shows up on the next frame.
+    pub fn set_catalog(&mut self, catalog: Catalog) {
+        self.catalog = catalog;
+    }
+
+    /// The current string catalog.
+    pub fn catalog<'a>(&'a self) -> &'a Catalog { &self.catalog }
+
+    /// Set a function that generates glyph images for chars that aren't
+    /// loaded in the font yet. Use this with eg. a TrueType rasterizer to
+    /// support large character sets without filling the atlas up front. The
+    /// atlas needs to have free space set aside with
+    /// CanvasBuilder::reserve_atlas_area for the generated glyphs.
+    pub fn set_glyph_source(&mut self, font: Font, source: Box<Fn(char) -> Option<GlyphImage>>) {
+        self.glyph_sources.insert(font, source);
+        self.missing_glyphs.clear();
+    }
+
+    /// Change the fallback font of a font while the canvas is running, see
+    /// CanvasBuilder::set_fallback_font.
+    pub fn set_fallback_font(&mut self, Font(idx): Font, fallback: Option<Font>) {
+        self.fonts[idx].fallback_font = fallback;
+        self.missing_glyphs.clear();
+    }
+
+    /// Change the fallback glyph of a font while the canvas is running, see
+    /// CanvasBuilder::set_fallback_glyph.
+    pub fn set_fallback_glyph(&mut self, Font(idx): Font, c: Option<char>) {
+        self.fonts[idx].fallback_glyph = c;
+        self.missing_glyphs.clear();
+    }
+
+    /// Load glyphs for the chars in text from the glyph sources of the font
+    /// and its fallback fonts. Fonter calls this for the text it's given,
+    /// call it yourself before using draw_char directly on text that may
+    /// have chars not loaded yet.
+    pub fn cache_glyphs(&mut self, font: Font, text: &str) {
+        for c in text.chars() {
+            if self.find_glyph(font, c).is_some() || self.missing_glyphs.contains(&(font, c)) {
+                continue;
+            }
+            if !self.load_glyph(font, c) {
+                self.missing_glyphs.insert((font, c));
+            }
         }
     }
 
+    /// Look up a char from a font and its fallback fonts.
+    fn find_glyph(&self, font: Font, c: char) -> Option<Glyph> {
+        let mut f = font;
+        // Bound the walk in case the fallback chain has a cycle.
+        for _ in 0..self.fonts.len() {
+            let data = self.font_data(f);
+            if let Some(&glyph) = data.glyph(c) { return Some(glyph); }
+            match data.fallback_font {
+                Some(next) => { f = next; }
+                None => { break; }
+            }
+        }
+        None
+    }
+
+    /// Try to generate a glyph for a char from the glyph sources in a font
+    /// chain and add it to the atlas.
+    fn load_glyph(&mut self, font: Font, c: char) -> bool {
+        let mut f = font;
+        for _ in 0..self.fonts.len() {
+            let generated = self.glyph_sources.get(&f).and_then(|source| (**source)(c));
+            if let Some(glyph) = generated {
+                match self.atlas.insert(glyph.offset, &glyph.image) {
+                    Some(idx) => {
+                        let Font(font_idx) = f;
+                        self.fonts[font_idx].insert_glyph(
+                            c, Glyph { image: Image(idx), advance: glyph.advance });
+                        self.atlas_dirty = true;
+                        return true;
+                    }
+                    // Out of atlas space.
+                    None => { return false; }
+                }
+            }
+            match self.font_data(f).fallback_font {
+                Some(next) => { f = next; }
+                None => { break; }
+            }
+        }
+        false
+    }
+
     /// Return a texture coordinate to a #FFFFFFFF texel for solid color
     /// graphics.
     pub fn solid_tex_coord(&self) -> V2<f32> { self.atlas.items[SOLID_IDX].tex.0 }
@@ -246,12 +461,57 @@ impl Canvas {
         self.renderer.canvas_pixels()
     }
 
+    /// Enter a new imgui ID scope identified by key. Widgets created in the
+    /// scope get ids distinct from the same code site in other scopes, so
+    /// helper functions or loops can be wrapped in push_id/pop_id pairs to
+    /// keep their widgets apart.
+    pub fn push_id<K: Hash + ?Sized>(&mut self, key: &K) {
+        self.id_stack.push(key);
+    }
+
+    /// Exit the innermost imgui ID scope.
+    pub fn pop_id(&mut self) {
+        self.id_stack.pop();
+    }
+
+    /// Map a widget id into the current ID scope. The hot and active widget
+    /// methods below do this themselves, so widgets can pass them the ids
+    /// from widget_id! as they are.
+    pub fn scoped_id(&self, id: WidgetId) -> WidgetId {
+        self.id_stack.scoped(id)
+    }
+
+    /// Mark the widget as being under the mouse cursor.
+    pub fn set_hot(&mut self, id: WidgetId) {
+        self.hot_widget = Some(self.scoped_id(id));
+    }
+
+    /// Mark the widget as being interacted with.
+    pub fn set_active(&mut self, id: WidgetId) {
+        self.active_widget = Some(self.scoped_id(id));
+    }
+
+    pub fn is_hot(&self, id: WidgetId) -> bool {
+        self.hot_widget == Some(self.scoped_id(id))
+    }
+
+    pub fn is_active(&self, id: WidgetId) -> bool {
+        self.active_widget == Some(self.scoped_id(id))
+    }
+
+    /// Return whether some widget, or a mouse drag that started outside
+    /// any widget, is being interacted with.
+    pub fn has_active(&self) -> bool {
+        self.active_widget.is_some()
+    }
+
     fn imgui_prepare(&mut self) {
         // Initial setup for imgui.
         self.hot_widget = None;
     }
 
     fn imgui_finish(&mut self) {
+        assert!(self.id_stack.is_empty(), "push_id without matching pop_id");
         if !self.mouse_pressed {
             self.active_widget = None;
         } else {
@@ -275,6 +535,12 @@ impl<'a> Iterator for Canvas {
 
             let mut target = self.display.draw();
 
+            if self.atlas_dirty {
+                self.renderer.set_atlas(
+                    &self.display, image::imageops::flip_vertical(&self.atlas.image));
+                self.atlas_dirty = false;
+            }
+
             // Move out the accumulated geometry data.
             let vertices = mem::replace(&mut self.vertices, Vec::new());
             let indices = mem::replace(&mut self.indices, Vec::new());
@@ -382,5 +648,14 @@ impl<'a> Iterator for Canvas {
 }
 
 /// Drawable images stored in the Canvas.
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub struct Image(usize);
+
+/// Fonts stored in the Canvas.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Font(usize);
+
+impl Default for Font {
+    /// The built-in 8x8 pixel font.
+    fn default() -> Font { Font(0) }
+}
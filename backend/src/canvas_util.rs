@@ -1,5 +1,5 @@
 use std::num::Float;
-use canvas::{Canvas, Image, FONT_W};
+use canvas::{Canvas, Image, Font};
 use util::{V2, Rect, Color, Rgba, color};
 use ::{WidgetId};
 use util::Anchor::*;
@@ -22,9 +22,9 @@ pub trait CanvasUtil {
     // TODO: More specs
     fn button(&mut self, id: WidgetId, pos: V2<f32>, z: f32) -> bool;
 
-    fn draw_char<C: Color+Copy, D: Color+Copy>(&mut self, c: char, offset: V2<f32>, z: f32, color: &C, border: Option<&D>);
+    fn draw_char<C: Color+Copy, D: Color+Copy>(&mut self, font: Font, c: char, offset: V2<f32>, z: f32, color: &C, border: Option<&D>);
 
-    fn char_width(&self, c: char) -> f32;
+    fn char_width(&self, font: Font, c: char) -> f32;
 }
 
 impl CanvasUtil for Canvas {
@@ -92,9 +92,9 @@ impl CanvasUtil for Canvas {
         let area = Rect(pos, V2(64.0, 16.0));
         let mut color = Rgba::parse("green");
         if area.contains(&self.mouse_pos) {
-            self.hot_widget = Some(id);
-            if self.active_widget.is_none() && self.mouse_pressed {
-                self.active_widget = Some(id);
+            self.set_hot(id);
+            if !self.has_active() && self.mouse_pressed {
+                self.set_active(id);
             }
             color = Rgba::parse("red");
         }
@@ -102,16 +102,17 @@ impl CanvasUtil for Canvas {
         self.fill_rect(&area, z, &color);
 
         return !self.mouse_pressed // Mouse is released
-            && self.active_widget == Some(id) // But this button is hot and active
-            && self.hot_widget == Some(id);
+            && self.is_active(id) // But this button is hot and active
+            && self.is_hot(id);
     }
 
-    fn draw_char<C: Color+Copy, D: Color+Copy>(&mut self, c: char, offset: V2<f32>, z: f32, color: &C, border: Option<&D>) {
+    fn draw_char<C: Color+Copy, D: Color+Copy>(&mut self, font: Font, c: char, offset: V2<f32>, z: f32, color: &C, border: Option<&D>) {
         static BORDER: [V2<f32>; 8] =
             [V2(-1.0, -1.0), V2( 0.0, -1.0), V2( 1.0, -1.0),
              V2(-1.0,  0.0),                 V2( 1.0,  0.0),
              V2(-1.0,  1.0), V2( 0.0,  1.0), V2( 1.0,  1.0)];
-        if let Some(img) = self.font_image(c) {
+        if let Some(glyph) = self.glyph(font, c) {
+            let img = glyph.image;
             if let Some(b) = border {
                 // Put the border a tiny bit further in the z-buffer so it
                 // won't clobber the text on the same layer.
@@ -124,19 +125,16 @@ impl CanvasUtil for Canvas {
         }
     }
 
-    fn char_width(&self, c: char) -> f32 {
-        // Special case for space, the atlas image won't have a width.
-        if c == ' ' { return (FONT_W / 2) as f32; }
-
-        // Infer letter width from the cropped atlas image. (Use mx instead of
-        // dim on the pos rectangle so that the left-side space will be
-        // preserved and the letters are kept slightly apart.)
-        if let Some(img) = self.font_image(c) {
-            let width = self.image_data(img).pos.mx().0;
-            return width;
+    fn char_width(&self, font: Font, c: char) -> f32 {
+        if let Some(glyph) = self.glyph(font, c) {
+            // Infer letter width from the cropped atlas image if the glyph
+            // doesn't specify it. (Use mx instead of dim on the pos rectangle
+            // so that the left-side space will be preserved and the letters
+            // are kept slightly apart.)
+            return glyph.advance.unwrap_or_else(|| self.image_data(glyph.image).pos.mx().0);
         }
 
         // Not a valid letter.
-        (FONT_W / 2) as f32
+        self.font_data(font).height / 2.0
     }
 }
@@ -0,0 +1,334 @@
+use std::fmt;
+use util::{V2, Rect, Rgb, color};
+use util::text::Map2DUtil;
+use canvas::{Canvas, Font};
+use canvas_util::CanvasUtil;
+
+/// A single character cell of a console.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Cell {
+    pub c: char,
+    pub fore: Rgb,
+    pub back: Rgb,
+    /// Outline color for the glyph.
+    pub border: Option<Rgb>,
+}
+
+impl Default for Cell {
+    fn default() -> Cell {
+        Cell {
+            c: ' ',
+            fore: color::LIGHTGRAY,
+            back: color::BLACK,
+            border: None,
+        }
+    }
+}
+
+/// Line styles for Console::draw_box.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BoxStyle {
+    /// Plus signs, dashes and bars, works with any font.
+    Ascii,
+    Single,
+    Double,
+}
+
+impl BoxStyle {
+    /// Top left, top right, bottom left, bottom right, horizontal and
+    /// vertical line chars.
+    fn chars(self) -> [char; 6] {
+        match self {
+            BoxStyle::Ascii => ['+', '+', '+', '+', '-', '|'],
+            BoxStyle::Single => ['┌', '┐', '└', '┘', '─', '│'],
+            BoxStyle::Double => ['╔', '╗', '╚', '╝', '═', '║'],
+        }
+    }
+}
+
+/// Fixed size grid of character cells, for text-mode style user
+/// interfaces.
+///
+/// The console keeps track of which cells have changed since the last call
+/// to `clean`, so that output backends that retain the screen contents
+/// only need to redraw those.
+pub struct Console {
+    size: V2<i32>,
+    cells: Vec<Cell>,
+    dirty: Vec<bool>,
+    /// Position where text written with write! goes.
+    pub cursor: V2<i32>,
+    /// Colors of the text printed on the console.
+    pub fore: Rgb,
+    pub back: Rgb,
+    pub border: Option<Rgb>,
+    /// Font to draw the console with. The cells are sized after the font's
+    /// line height and the width of 'M'.
+    pub font: Font,
+}
+
+impl Console {
+    pub fn new(width: u32, height: u32) -> Console {
+        let n = (width * height) as usize;
+        let cell: Cell = Default::default();
+        Console {
+            size: V2(width as i32, height as i32),
+            cells: (0..n).map(|_| cell).collect(),
+            dirty: (0..n).map(|_| true).collect(),
+            cursor: V2(0, 0),
+            fore: cell.fore,
+            back: cell.back,
+            border: cell.border,
+            font: Default::default(),
+        }
+    }
+
+    /// Size of the console in cells.
+    pub fn size(&self) -> V2<i32> { self.size }
+
+    fn index(&self, pos: V2<i32>) -> Option<usize> {
+        if Rect(V2(0, 0), self.size).contains(&pos) {
+            Some((pos.0 + pos.1 * self.size.0) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Return the cell at a position, or None if the position is outside
+    /// the console.
+    pub fn get(&self, pos: V2<i32>) -> Option<Cell> {
+        self.index(pos).map(|i| self.cells[i])
+    }
+
+    /// Set a cell. Positions outside the console are ignored.
+    pub fn set(&mut self, pos: V2<i32>, cell: Cell) {
+        if let Some(i) = self.index(pos) {
+            if self.cells[i] != cell {
+                self.cells[i] = cell;
+                self.dirty[i] = true;
+            }
+        }
+    }
+
+    /// Set a char with the current console colors.
+    pub fn put_char(&mut self, pos: V2<i32>, c: char) {
+        let cell = Cell { c: c, fore: self.fore, back: self.back, border: self.border };
+        self.set(pos, cell);
+    }
+
+    /// Fill an area with copies of a cell.
+    pub fn fill(&mut self, area: &Rect<i32>, cell: Cell) {
+        for pos in area.iter() { self.set(pos, cell); }
+    }
+
+    /// Clear the console to empty cells with the current colors and move
+    /// the cursor to the top left corner.
+    pub fn clear(&mut self) {
+        let cell = Cell { c: ' ', fore: self.fore, back: self.back, border: self.border };
+        let area = Rect(V2(0, 0), self.size);
+        self.fill(&area, cell);
+        self.cursor = V2(0, 0);
+    }
+
+    /// Print text starting from pos with the current colors. Newlines in
+    /// the text continue printing from the start column of the next row.
+    /// Text that goes past the edge of the console is clipped.
+    pub fn print(&mut self, pos: V2<i32>, text: &str) {
+        for (c, x, y) in text.chars().map2d() {
+            self.put_char(pos + V2(x, y), c);
+        }
+    }
+
+    /// Draw the outline of a box with line drawing characters.
+    pub fn draw_box(&mut self, area: &Rect<i32>, style: BoxStyle) {
+        let (V2(x0, y0), V2(x1, y1)) = (area.mn(), area.mx() - V2(1, 1));
+        if x1 < x0 || y1 < y0 { return; }
+        let chars = style.chars();
+        for x in x0 + 1..x1 {
+            self.put_char(V2(x, y0), chars[4]);
+            self.put_char(V2(x, y1), chars[4]);
+        }
+        for y in y0 + 1..y1 {
+            self.put_char(V2(x0, y), chars[5]);
+            self.put_char(V2(x1, y), chars[5]);
+        }
+        self.put_char(V2(x0, y0), chars[0]);
+        self.put_char(V2(x1, y0), chars[1]);
+        self.put_char(V2(x0, y1), chars[2]);
+        self.put_char(V2(x1, y1), chars[3]);
+    }
+
+    /// Return whether the cell at pos has changed since the last clean.
+    pub fn is_dirty(&self, pos: V2<i32>) -> bool {
+        self.index(pos).map_or(false, |i| self.dirty[i])
+    }
+
+    /// Return the positions of the cells that have changed since the last
+    /// clean.
+    pub fn dirty_cells(&self) -> Vec<V2<i32>> {
+        Rect(V2(0, 0), self.size).iter().filter(|&p| self.is_dirty(p)).collect()
+    }
+
+    /// Mark all cells as unchanged.
+    pub fn clean(&mut self) {
+        for d in self.dirty.iter_mut() { *d = false; }
+    }
+
+    /// Mark all cells as changed, eg. when the output needs a full redraw.
+    pub fn invalidate(&mut self) {
+        for d in self.dirty.iter_mut() { *d = true; }
+    }
+
+    /// Size of a console cell in pixels when drawn with the console font.
+    pub fn cell_size(&self, canvas: &Canvas) -> V2<f32> {
+        V2(canvas.char_width(self.font, 'M'), canvas.font_data(self.font).height)
+    }
+
+    /// Draw the console on the canvas with its top left corner at offset.
+    pub fn draw(&self, canvas: &mut Canvas, offset: V2<f32>, z: f32) {
+        let dim = self.cell_size(canvas);
+        let ascent = canvas.font_data(self.font).ascent;
+
+        // Draw the backgrounds as horizontal strips of the same color to
+        // keep the vertex count down.
+        for y in 0..self.size.1 {
+            let mut x = 0;
+            while x < self.size.0 {
+                let back = self.cells[(x + y * self.size.0) as usize].back;
+                let start = x;
+                while x < self.size.0 && self.cells[(x + y * self.size.0) as usize].back == back { x += 1; }
+                let rect = Rect(
+                    offset + V2(start as f32 * dim.0, y as f32 * dim.1),
+                    V2((x - start) as f32 * dim.0, dim.1));
+                // Push the backgrounds slightly behind the glyphs.
+                canvas.fill_rect(&rect, z + 0.0001, &back);
+            }
+        }
+
+        for y in 0..self.size.1 {
+            for x in 0..self.size.0 {
+                let cell = self.cells[(x + y * self.size.0) as usize];
+                if cell.c == ' ' { continue; }
+                let pos = offset + V2(x as f32 * dim.0, y as f32 * dim.1 + ascent);
+                canvas.draw_char(self.font, cell.c, pos, z, &cell.fore, cell.border.as_ref());
+            }
+        }
+    }
+}
+
+/// Writing to the console prints at the cursor and moves the cursor
+/// forward, wrapping at the right edge. Use with write! for formatted
+/// output.
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if c == '\n' {
+                self.cursor = V2(0, self.cursor.1 + 1);
+                continue;
+            }
+            if (c as u32) < 32 { continue; }
+            if self.cursor.0 >= self.size.0 {
+                self.cursor = V2(0, self.cursor.1 + 1);
+            }
+            let pos = self.cursor;
+            self.put_char(pos, c);
+            self.cursor.0 += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use util::V2;
+    use super::Console;
+
+    fn row(con: &Console, y: i32) -> String {
+        (0..con.size().0).map(|x| con.get(V2(x, y)).unwrap().c).collect()
+    }
+
+    #[test]
+    fn test_print() {
+        use std::fmt::Write;
+
+        let mut con = Console::new(5, 3);
+        con.print(V2(3, 0), "abc\nde");
+        assert_eq!("   ab", row(&con, 0));
+        assert_eq!("   de", row(&con, 1));
+        con.print(V2(-1, 2), "xyz");
+        assert_eq!("yz   ", row(&con, 2));
+
+        con.clear();
+        assert_eq!(V2(0, 0), con.cursor);
+        write!(&mut con, "{}", "abcdefg\nhi").unwrap();
+        assert_eq!("abcde", row(&con, 0));
+        assert_eq!("fg   ", row(&con, 1));
+        assert_eq!("hi   ", row(&con, 2));
+        assert_eq!(V2(2, 2), con.cursor);
+
+        con.put_char(V2(4, 2), 'z');
+        con.put_char(V2(5, 2), 'w');
+        assert_eq!("hi  z", row(&con, 2));
+        assert_eq!(None, con.get(V2(5, 2)));
+    }
+
+    #[test]
+    fn test_draw_box() {
+        use util::Rect;
+        use super::BoxStyle;
+
+        let mut con = Console::new(4, 3);
+        con.draw_box(&Rect(V2(0, 0), V2(4, 3)), BoxStyle::Ascii);
+        assert_eq!("+--+", row(&con, 0));
+        assert_eq!("|  |", row(&con, 1));
+        assert_eq!("+--+", row(&con, 2));
+        con.draw_box(&Rect(V2(0, 0), V2(4, 3)), BoxStyle::Single);
+        assert_eq!("┌──┐", row(&con, 0));
+        assert_eq!("│  │", row(&con, 1));
+        assert_eq!("└──┘", row(&con, 2));
+        con.draw_box(&Rect(V2(0, 0), V2(4, 3)), BoxStyle::Double);
+        assert_eq!("╔══╗", row(&con, 0));
+        assert_eq!("║  ║", row(&con, 1));
+        assert_eq!("╚══╝", row(&con, 2));
+
+        // Degenerate boxes.
+        con.clear();
+        con.draw_box(&Rect(V2(1, 1), V2(1, 1)), BoxStyle::Ascii);
+        assert_eq!(" +  ", row(&con, 1));
+        con.draw_box(&Rect(V2(0, 0), V2(0, 3)), BoxStyle::Ascii);
+        assert_eq!("    ", row(&con, 0));
+    }
+
+    #[test]
+    fn test_dirty() {
+        use util::{Rect, color};
+        use super::Cell;
+
+        let mut con = Console::new(4, 3);
+        assert_eq!(12, con.dirty_cells().len());
+        con.clean();
+        assert!(con.dirty_cells().is_empty());
+
+        // Setting a cell to what it already is doesn't dirty it.
+        con.put_char(V2(1, 1), ' ');
+        assert!(!con.is_dirty(V2(1, 1)));
+        con.put_char(V2(1, 1), 'x');
+        assert_eq!(vec![V2(1, 1)], con.dirty_cells());
+        assert!(!con.is_dirty(V2(9, 9)));
+
+        con.clean();
+        let cell = Cell { c: '#', back: color::RED, ..Default::default() };
+        con.fill(&Rect(V2(2, 1), V2(5, 5)), cell);
+        assert_eq!(vec![V2(2, 1), V2(3, 1), V2(2, 2), V2(3, 2)], con.dirty_cells());
+        assert_eq!(Some(cell), con.get(V2(3, 2)));
+
+        con.clean();
+        con.clear();
+        assert_eq!(vec![V2(1, 1), V2(2, 1), V2(3, 1), V2(2, 2), V2(3, 2)], con.dirty_cells());
+        assert!(con.get(V2(3, 2)).unwrap().c == ' ');
+
+        con.clean();
+        con.invalidate();
+        assert_eq!(12, con.dirty_cells().len());
+    }
+}
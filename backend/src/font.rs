@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use image::{ImageBuffer, Rgba};
+use util::V2;
+use util::text::FontMetrics;
+use canvas::{Canvas, Image, Font};
+use canvas_util::CanvasUtil;
+
+/// Metrics and glyph images of a font stored in the Canvas.
+#[derive(Clone)]
+pub struct FontData {
+    /// Distance between the baselines of successive lines.
+    pub height: f32,
+    /// Distance from the top of a line to the baseline.
+    pub ascent: f32,
+    /// Font to look up chars that are missing from this font from.
+    pub fallback_font: Option<Font>,
+    /// Char to draw in place of chars that are missing from this font and
+    /// its fallback fonts.
+    pub fallback_glyph: Option<char>,
+    glyphs: HashMap<char, Glyph>,
+    kerning: HashMap<(char, char), f32>,
+}
+
+/// A single drawable character of a font.
+#[derive(Copy, Clone, Debug)]
+pub struct Glyph {
+    /// Atlas image of the glyph, with the draw offset relative to the
+    /// baseline.
+    pub image: Image,
+    /// How much to move the pen after drawing the glyph. If None, the width
+    /// is inferred from the right edge of the atlas image.
+    pub advance: Option<f32>,
+}
+
+impl FontData {
+    pub fn new(height: f32, ascent: f32) -> FontData {
+        FontData {
+            height: height,
+            ascent: ascent,
+            fallback_font: None,
+            fallback_glyph: None,
+            glyphs: HashMap::new(),
+            kerning: HashMap::new(),
+        }
+    }
+
+    pub fn insert_glyph(&mut self, c: char, glyph: Glyph) {
+        self.glyphs.insert(c, glyph);
+    }
+
+    pub fn insert_kerning(&mut self, left: char, right: char, amount: f32) {
+        self.kerning.insert((left, right), amount);
+    }
+
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs.get(&c)
+    }
+
+    /// Return the extra horizontal adjustment for drawing right after left.
+    pub fn kerning(&self, left: char, right: char) -> f32 {
+        self.kerning.get(&(left, right)).map_or(0.0, |&x| x)
+    }
+}
+
+/// Glyph image produced on demand by a dynamic glyph source.
+pub struct GlyphImage {
+    pub image: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    /// Draw offset of the image relative to the pen position on the
+    /// baseline.
+    pub offset: V2<i32>,
+    /// How much to move the pen after drawing the glyph. If None, the width
+    /// is inferred from the right edge of the image.
+    pub advance: Option<f32>,
+}
+
+/// Text layout metrics of a font stored in a canvas.
+pub struct CanvasFont<'a> {
+    pub canvas: &'a Canvas,
+    pub font: Font,
+}
+
+impl<'a> FontMetrics for CanvasFont<'a> {
+    fn line_height(&self) -> f32 { self.canvas.font_data(self.font).height }
+
+    fn ascent(&self) -> f32 { self.canvas.font_data(self.font).ascent }
+
+    fn char_width(&self, c: char) -> f32 { self.canvas.char_width(self.font, c) }
+
+    fn kerning(&self, left: char, right: char) -> f32 {
+        self.canvas.font_data(self.font).kerning(left, right)
+    }
+
+    fn icon_width(&self, name: &str) -> f32 {
+        match self.canvas.icon(name) {
+            Some(img) => self.canvas.image_data(img).pos.mx().0,
+            None => 0.0
+        }
+    }
+}
@@ -1,54 +1,104 @@
-use util::{Rgba, V2, Rect, Color, color, Anchor};
-use util::text;
-use canvas::{Canvas, FONT_H};
+use util::{Rgba, V2, Color, color, Anchor};
+use util::text::{self, Run, Style, Align, Direction, Glyph, Layout};
+use util::timing::{self, Typewriter};
+use util::locale::Args;
+use canvas::{Canvas, Font};
 use canvas_util::{CanvasUtil};
-
-pub enum Align {
-    Left,
-    Right,
-    Center
-}
+use font::{CanvasFont};
 
 pub struct Fonter<'a> {
     canvas: &'a mut Canvas,
+    font: Font,
     anchor: Anchor,
     align: Align,
+    direction: Option<Direction>,
     color: Rgba,
     z: f32,
     max_lines: Option<usize>,
     border: Option<Rgba>,
     max_width: Option<f32>,
-    /// (Text, width) pairs.
-    lines: Vec<(String, f32)>,
-    longest_line_width: f32,
+    runs: Vec<Run>,
+    reveal: Option<Typewriter>,
+    effects: Vec<TextEffect>,
+}
+
+/// Animated effects for Fonter text.
+#[derive(Copy, Clone, Debug)]
+pub enum TextEffect {
+    /// Bob the letters up and down in a wave.
+    Wobble { amplitude: f32, period_s: f64 },
+    /// Cycle the text color between its own color and another color.
+    Pulse { color: Rgba, period_s: f64 },
+}
+
+impl TextEffect {
+    fn apply(&self, idx: usize, pos: &mut V2<f32>, color: &mut Rgba) {
+        match *self {
+            TextEffect::Wobble { amplitude, period_s } => {
+                // Successive letters lag a bit behind in the wave.
+                pos.1 = pos.1 + amplitude * timing::wave(period_s, idx as f64 * -0.1) as f32;
+            }
+            TextEffect::Pulse { color: pulse, period_s } => {
+                let t = ((timing::wave(period_s, 0.0) + 1.0) / 2.0) as f32;
+                let (a, b) = (color.to_rgba(), pulse.to_rgba());
+                *color = Color::from_rgba([
+                    a[0] + (b[0] - a[0]) * t,
+                    a[1] + (b[1] - a[1]) * t,
+                    a[2] + (b[2] - a[2]) * t,
+                    a[3] + (b[3] - a[3]) * t]);
+            }
+        }
+    }
 }
 
 impl<'a> Fonter<'a> {
     pub fn new(canvas: &'a mut Canvas) -> Fonter<'a> {
         Fonter {
             canvas: canvas,
+            font: Default::default(),
             anchor: Anchor::TopLeft,
-            align: Align::Left,
+            align: Align::Start,
+            direction: None,
             color: Color::from_color(&color::WHITE),
             z: 0.1,
             max_lines: None,
             border: None,
             max_width: None,
-            lines: vec![(String::new(), 0.0)],
-            longest_line_width: 0.0,
+            runs: Vec::new(),
+            reveal: None,
+            effects: Vec::new(),
         }
     }
 
+    /// Set the font to draw with. The default is the built-in 8x8 font.
+    pub fn font(mut self, font: Font) -> Fonter<'a> {
+        self.font = font;
+        // Glyphs for the new font may need loading.
+        for run in self.runs.iter() {
+            if let &Run::Text(ref txt, _) = run {
+                self.canvas.cache_glyphs(self.font, &txt[..]);
+            }
+        }
+        self
+    }
+
     /// Set the point of the text box which draw offset will anchor to.
     pub fn anchor(mut self, anchor: Anchor) -> Fonter<'a> {
         self.anchor = anchor; self
     }
 
-    /// Set the text alignment
+    /// Set the text alignment. The default is Start, which aligns the text
+    /// left or right depending on its direction.
     pub fn align(mut self, align: Align) -> Fonter<'a> {
         self.align = align; self
     }
 
+    /// Set the base direction of the text. If not set, the direction is
+    /// detected from the first strongly directional letter of the text.
+    pub fn direction(mut self, direction: Direction) -> Fonter<'a> {
+        self.direction = Some(direction); self
+    }
+
     /// Set text color. The default color is white.
     pub fn color<C: Color>(mut self, color: &C) -> Fonter<'a> {
         self.color = Color::from_color(color); self
@@ -73,70 +123,110 @@ impl<'a> Fonter<'a> {
     /// Set the maximum number of lines to draw (lines of text before this are
     /// dropped).
     pub fn max_lines(mut self, max_lines: usize) -> Fonter<'a> {
-        self.max_lines = Some(max_lines);
-        self.cull_lines();
-        self
+        self.max_lines = Some(max_lines); self
+    }
+
+    /// Reveal the text progressively with a typewriter. The text is laid out
+    /// in full before revealing, so words don't jump between lines as they
+    /// appear.
+    pub fn reveal(mut self, typewriter: &Typewriter) -> Fonter<'a> {
+        self.reveal = Some(*typewriter); self
+    }
+
+    /// Add an animated effect to the text.
+    pub fn effect(mut self, effect: TextEffect) -> Fonter<'a> {
+        self.effects.push(effect); self
     }
 
     /// Append to the fonter text.
-    pub fn text(mut self, txt: String) -> Fonter<'a> {
-        assert!(self.lines.len() > 0);
-        // The last line can be added to, snip it off.
-        let mut new_txt = format!("{}{}", self.lines[self.lines.len() - 1].0, txt);
-        let new_len = self.lines.len() - 1;
-        self.lines.truncate(new_len);
-        if let Some(w) = self.max_width {
-            new_txt = text::wrap_lines(&new_txt[..], &|c| self.canvas.char_width(c), w);
-        }
+    pub fn text(self, txt: String) -> Fonter<'a> {
+        self.runs(vec![Run::Text(txt, Style::default())])
+    }
 
-        let mut new_lines: Vec<(String, f32)> = new_txt.split('\n').map(|s| (s.to_string(), self.str_width(s))).collect();
-        self.lines.append(&mut new_lines);
-        assert!(self.lines.len() > 0);
+    /// Append a string from the string catalog of the canvas, see
+    /// Catalog::tr.
+    pub fn tr(self, key: &str, args: &Args) -> Fonter<'a> {
+        let txt = self.canvas.catalog().tr(key, args);
+        self.text(txt)
+    }
 
-        self.cull_lines();
-        self
+    /// Append text with inline markup to the fonter, see
+    /// text::parse_markup for the syntax. Panics if the markup doesn't
+    /// parse, use for constant strings.
+    pub fn markup(self, txt: &str) -> Fonter<'a> {
+        match text::parse_markup(txt) {
+            Ok(runs) => self.runs(runs),
+            Err(e) => panic!("Bad markup {:?}: {}", txt, e)
+        }
     }
 
-    fn cull_lines(&mut self) {
-        if let Some(n) = self.max_lines {
-            if self.lines.len() > n {
-                let new_len = self.lines.len() - n;
-                self.lines = self.lines.split_off(new_len);
+    /// Append styled text runs to the fonter.
+    pub fn runs(mut self, runs: Vec<Run>) -> Fonter<'a> {
+        for run in runs.iter() {
+            if let &Run::Text(ref txt, _) = run {
+                self.canvas.cache_glyphs(self.font, &txt[..]);
             }
         }
-        self.set_longest_width();
+        self.runs.extend(runs.into_iter());
+        self
     }
 
-    fn set_longest_width(&mut self) {
-        self.longest_line_width = self.lines.iter().map(|x| x.1)
-            .fold(0.0, |a, w| if w > a { w } else { a });
+    /// Lay out the text without drawing it. The layout positions are
+    /// relative to the top left corner of the text box, use this to find out
+    /// the size of the text before drawing.
+    pub fn layout(&self) -> Layout {
+        text::layout(
+            &CanvasFont { canvas: &*self.canvas, font: self.font },
+            &self.runs[..], self.max_width, self.align, self.direction, self.max_lines)
     }
 
-    pub fn draw(&mut self, offset: V2<f32>) {
-        let anchor_points = Rect(
-            V2(0.0, 0.0),
-            V2(self.longest_line_width,
-               (self.lines.len() * FONT_H as usize) as f32));
-        let offset = offset - anchor_points.point(self.anchor);
-        // TODO Anchoring
-        for (row, s) in self.lines.iter().enumerate() {
-            let y = offset.1 + FONT_H as f32 + row as f32 * FONT_H as f32;
-            let line_width = s.1;
-            let mut x = offset.0 + match self.align {
-                Align::Left => 0.0,
-                Align::Right => (self.longest_line_width - line_width),
-                Align::Center => (self.longest_line_width - line_width) / 2.0,
-            };
-            for c in s.0.chars() {
-                self.canvas.draw_char(c, V2(x, y), self.z, &self.color, self.border.as_ref());
-                x += self.canvas.char_width(c);
-            }
+    /// Return whether the typewriter set with reveal has revealed all of the
+    /// text. Always true if there is no typewriter.
+    pub fn is_revealed(&self) -> bool {
+        match self.reveal {
+            Some(ref tw) => tw.is_finished(reveal_chars(&self.layout()).iter().cloned()),
+            None => true
         }
     }
 
-
-    fn str_width(&self, s: &str) -> f32 {
-        s.chars().fold(0.0, |a, c| a + self.canvas.char_width(c))
+    pub fn draw(&mut self, offset: V2<f32>) {
+        let layout = self.layout();
+        let offset = offset - layout.bounds.point(self.anchor);
+        let visible = match self.reveal {
+            Some(ref tw) => tw.visible(reveal_chars(&layout).iter().cloned()),
+            None => layout.glyphs.len()
+        };
+        for (i, g) in layout.glyphs.iter().take(visible).enumerate() {
+            let mut pos = offset + g.pos;
+            let mut color = g.style.color.unwrap_or(self.color);
+            for e in self.effects.iter() { e.apply(i, &mut pos, &mut color); }
+            match g.glyph {
+                Glyph::Char(c) => {
+                    self.canvas.draw_char(self.font, c, pos, self.z, &color, self.border.as_ref());
+                    if g.style.emphasis {
+                        // Fake a bold face by drawing the letter again one
+                        // pixel to the right.
+                        self.canvas.draw_char(self.font, c, pos + V2(1.0, 0.0), self.z, &color, self.border.as_ref());
+                    }
+                }
+                Glyph::Icon(ref name) => {
+                    // Icon images are positioned relative to the baseline
+                    // like glyphs. They keep their own colors, but take the
+                    // alpha of the text so that they fade with it.
+                    if let Some(img) = self.canvas.icon(&name[..]) {
+                        let tint = Rgba::new(0xff, 0xff, 0xff, color.a);
+                        self.canvas.draw_image(img, pos, self.z, &tint, &color::BLACK);
+                    }
+                }
+            }
+        }
     }
+}
 
+/// The chars the typewriter reveals for the layout, icons count as spaces.
+fn reveal_chars(layout: &Layout) -> Vec<char> {
+    layout.glyphs.iter().map(|g| match g.glyph {
+        Glyph::Char(c) => c,
+        Glyph::Icon(_) => ' '
+    }).collect()
 }
@@ -16,19 +16,34 @@ extern crate "calx_util" as util;
 extern crate time;
 extern crate image;
 
+use std::hash::{Hash, Hasher, SipHasher};
+
 pub use canvas::{CanvasBuilder, Canvas};
-pub use canvas::{Image};
+pub use canvas::{Image, Font};
+pub use font::{FontData, Glyph, GlyphImage, CanvasFont};
 pub use canvas_util::{CanvasUtil};
+pub use console::{Console, Cell, BoxStyle};
 pub use key::Key;
-pub use fonter::{Fonter, Align};
+pub use fonter::{Fonter, TextEffect};
+pub use util::text::{Align, Direction};
 pub use event::{Event, MouseButton};
+pub use terminal::{Terminal, ColorMode, InputParser, xterm_color};
+#[cfg(unix)]
+pub use terminal::set_raw_mode;
+pub use message_log::{MessageLog, Message};
+pub use tile_renderer::TileRenderer;
 
 mod canvas;
 mod canvas_util;
+mod console;
 mod event;
+mod font;
 mod fonter;
 mod key;
+mod message_log;
 mod renderer;
+mod terminal;
+mod tile_renderer;
 
 #[cfg(target_os = "macos")]
 mod scancode_macos;
@@ -46,12 +61,16 @@ mod scancode {
     pub use scancode_windows::MAP;
 }
 
-/// UI Widget static identifier, unique for a specific site in source code.
-#[derive(Copy, Debug, PartialEq)]
+/// UI Widget static identifier, unique for a specific site in source code
+/// and a specific ID scope.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct WidgetId {
     filename: &'static str,
     line: usize,
     column: usize,
+    /// Hash of the keys that distinguish between multiple widgets generated
+    /// from the same source code site.
+    scope: u64,
 }
 
 impl WidgetId {
@@ -60,6 +79,7 @@ impl WidgetId {
             filename: filename,
             line: line,
             column: column,
+            scope: 0,
         }
     }
 
@@ -68,19 +88,108 @@ impl WidgetId {
             filename: "n/a",
             line: 666666,
             column: 666666,
+            scope: 0,
         }
     }
+
+    /// Derive a new id from this one and a key value. Use this to tell apart
+    /// widgets that are generated in a loop from the same code site.
+    pub fn with_key<K: Hash + ?Sized>(self, key: &K) -> WidgetId {
+        WidgetId { scope: scope_hash(self.scope, key), ..self }
+    }
+
+    /// Place the id in an ID scope, see Canvas::push_id.
+    pub fn in_scope(self, scope: u64) -> WidgetId {
+        self.with_key(&scope)
+    }
+}
+
+/// Stack of nested imgui ID scopes.
+#[derive(Clone, Debug, Default)]
+struct IdStack(Vec<u64>);
+
+impl IdStack {
+    /// Enter a scope nested in the current innermost one.
+    fn push<K: Hash + ?Sized>(&mut self, key: &K) {
+        let parent = self.0.last().map_or(0, |&x| x);
+        self.0.push(scope_hash(parent, key));
+    }
+
+    fn pop(&mut self) {
+        assert!(self.0.len() > 0, "pop_id without matching push_id");
+        self.0.pop();
+    }
+
+    fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// Map a widget id into the innermost scope.
+    fn scoped(&self, id: WidgetId) -> WidgetId {
+        match self.0.last() {
+            Some(&scope) => id.in_scope(scope),
+            None => id
+        }
+    }
+}
+
+/// Combine a parent scope hash and a key into a new scope hash.
+fn scope_hash<K: Hash + ?Sized>(parent: u64, key: &K) -> u64 {
+    let mut hasher = SipHasher::new();
+    parent.hash(&mut hasher);
+    key.hash(&mut hasher);
+    hasher.finish()
 }
 
 #[macro_export]
 /// Generate a static identifier for the current source code position. Used
-/// with imgui API.
+/// with imgui API. An optional key expression can be given to make unique
+/// identifiers for widgets generated in a loop, eg. `widget_id!(i)`.
 macro_rules! widget_id {
     () => {
-        // XXX: Assuming the crate user renames 'calx_backend' to 'backend'
-        // when declaring the extern crate, since the app that does use this
-        // does that. This is extremely wrong and bad to assume. Don't know
-        // how to do this right.
-        backend::WidgetId::new(concat!(module_path!(), "/", file!()), line!(), column!())
+        $crate::WidgetId::new(concat!(module_path!(), "/", file!()), line!(), column!())
+    };
+    ($key:expr) => {
+        $crate::WidgetId::new(concat!(module_path!(), "/", file!()), line!(), column!())
+            .with_key(&$key)
+    };
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_id_scopes() {
+        use super::IdStack;
+
+        let id = widget_id!();
+        let mut stack = IdStack::default();
+        assert_eq!(id, stack.scoped(id));
+
+        stack.push("a");
+        let a = stack.scoped(id);
+        assert!(a != id);
+        stack.push(&1);
+        let a1 = stack.scoped(id);
+        stack.pop();
+        // Back in the outer scope.
+        assert_eq!(a, stack.scoped(id));
+        stack.push(&2);
+        let a2 = stack.scoped(id);
+        stack.pop();
+        stack.pop();
+        assert!(stack.is_empty());
+
+        // The same inner key in a different outer scope is a different
+        // widget.
+        stack.push("b");
+        stack.push(&1);
+        let b1 = stack.scoped(id);
+        stack.pop();
+        stack.pop();
+        assert!(a1 != a2 && a1 != b1 && a1 != a);
+        assert!(a1 != widget_id!(1));
+
+        // Scopes are reproducible between frames.
+        stack.push("a");
+        stack.push(&1);
+        assert_eq!(a1, stack.scoped(id));
     }
 }
@@ -0,0 +1,243 @@
+use std::collections::VecDeque;
+use time;
+use util::{V2, Rect, Rgba, Color, Anchor};
+use util::text::{self, Run, Style};
+use canvas::{Canvas, Font};
+use event::Event;
+use fonter::Fonter;
+use key::Key;
+
+/// A single entry in a message log.
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub text: Vec<Run>,
+    /// Game turn when the message was last posted.
+    pub turn: u64,
+    /// Time in seconds when the message was last posted.
+    pub time_s: f64,
+    /// How many times the message was posted in a row.
+    pub count: usize,
+}
+
+/// Scrolling log of game messages with a bounded history.
+pub struct MessageLog {
+    messages: VecDeque<Message>,
+    capacity: usize,
+    /// How many messages the view is scrolled back from the newest one.
+    scroll: usize,
+    /// Font to draw the messages with.
+    pub font: Font,
+    pub color: Rgba,
+    /// Messages older than this start fading out when the log isn't
+    /// scrolled back.
+    pub fade_delay_s: f64,
+    /// How long it takes for a message to fade out completely. If None,
+    /// messages don't fade.
+    pub fade_duration_s: Option<f64>,
+    /// Whether to prefix the messages with their turn numbers.
+    pub show_turns: bool,
+}
+
+impl MessageLog {
+    /// Create a new log that keeps at most capacity messages.
+    pub fn new(capacity: usize) -> MessageLog {
+        assert!(capacity > 0);
+        MessageLog {
+            messages: VecDeque::new(),
+            capacity: capacity,
+            scroll: 0,
+            font: Default::default(),
+            color: Rgba::new(0xff, 0xff, 0xff, 0xff),
+            fade_delay_s: 8.0,
+            fade_duration_s: Some(2.0),
+            show_turns: false,
+        }
+    }
+
+    /// Post a message with inline markup, see text::parse_markup. A message
+    /// that repeats the previous one is merged into it with a repeat count.
+    /// Panics if the markup doesn't parse.
+    pub fn add(&mut self, turn: u64, markup: &str) {
+        match text::parse_markup(markup) {
+            Ok(runs) => self.add_runs(turn, runs),
+            Err(e) => panic!("Bad markup {:?}: {}", markup, e)
+        }
+    }
+
+    /// Post a message of styled text runs.
+    pub fn add_runs(&mut self, turn: u64, runs: Vec<Run>) {
+        let now = time::precise_time_s();
+        if let Some(last) = self.messages.back_mut() {
+            if last.text == runs {
+                last.count += 1;
+                last.turn = turn;
+                last.time_s = now;
+                return;
+            }
+        }
+
+        self.messages.push_back(Message { text: runs, turn: turn, time_s: now, count: 1 });
+        if self.messages.len() > self.capacity {
+            self.messages.pop_front();
+        }
+        // Keep a scrolled back view on the same messages.
+        if self.scroll > 0 {
+            self.scroll = ::std::cmp::min(self.scroll + 1, self.messages.len() - 1);
+        }
+    }
+
+    /// The messages in the log, from oldest to newest.
+    pub fn messages<'a>(&'a self) -> &'a VecDeque<Message> { &self.messages }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+        self.scroll = 0;
+    }
+
+    /// Scroll the view by a number of messages, positive values go back in
+    /// history.
+    pub fn scroll(&mut self, delta: i32) {
+        if self.messages.is_empty() { return; }
+        let max = self.messages.len() as i32 - 1;
+        let scroll = self.scroll as i32 + delta;
+        self.scroll = if scroll < 0 { 0 } else if scroll > max { max as usize } else { scroll as usize };
+    }
+
+    /// Return whether the view is scrolled back from the newest message.
+    pub fn is_scrolled(&self) -> bool { self.scroll > 0 }
+
+    /// Scroll the log with the mouse wheel and the PageUp, PageDown and End
+    /// keys. Returns whether the event was used.
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        match *event {
+            Event::MouseWheel(delta) => { self.scroll(delta); true }
+            Event::KeyPressed(Key::PageUp) => { self.scroll(1); true }
+            Event::KeyPressed(Key::PageDown) => { self.scroll(-1); true }
+            Event::KeyPressed(Key::End) => { self.scroll = 0; true }
+            _ => false
+        }
+    }
+
+    /// Draw the log in the given screen area, newest message at the bottom.
+    /// Long messages are wrapped to the width of the area and the messages
+    /// that don't fit are left out.
+    pub fn draw(&self, canvas: &mut Canvas, area: &Rect<f32>, z: f32) {
+        let now = time::precise_time_s();
+        let mut y = area.point(Anchor::BottomLeft).1;
+
+        for msg in self.messages.iter().rev().skip(self.scroll) {
+            // Everything is fully visible when browsing the history.
+            let alpha = match self.fade_duration_s {
+                Some(d) if self.scroll == 0 => {
+                    let age = now - msg.time_s - self.fade_delay_s;
+                    if age <= 0.0 { 1.0 } else { 1.0 - (age / d) as f32 }
+                }
+                _ => 1.0
+            };
+            // Messages are ordered by age, so the rest are faded out too.
+            if alpha <= 0.0 { break; }
+
+            let mut runs = Vec::new();
+            if self.show_turns {
+                runs.push(Run::Text(format!("[{}] ", msg.turn), Style::default()));
+            }
+            runs.extend(msg.text.iter().cloned());
+            if msg.count > 1 {
+                runs.push(Run::Text(format!(" x{}", msg.count), Style::default()));
+            }
+            let runs = runs.into_iter().map(|r| fade(r, alpha)).collect();
+
+            let mut fonter = Fonter::new(canvas)
+                .font(self.font)
+                .color(&fade_color(self.color, alpha))
+                .width((area.1).0)
+                .anchor(Anchor::BottomLeft)
+                .layer(z)
+                .runs(runs);
+            let height = (fonter.layout().bounds.1).1;
+            if y - height < (area.0).1 { break; }
+            fonter.draw(V2((area.0).0, y));
+            y = y - height;
+        }
+
+        fn fade(run: Run, alpha: f32) -> Run {
+            match run {
+                Run::Text(s, style) => Run::Text(s, fade_style(style, alpha)),
+                Run::Icon(s, style) => Run::Icon(s, fade_style(style, alpha)),
+            }
+        }
+
+        fn fade_style(style: Style, alpha: f32) -> Style {
+            Style { color: style.color.map(|c| fade_color(c, alpha)), ..style }
+        }
+
+        fn fade_color(color: Rgba, alpha: f32) -> Rgba {
+            let mut c = color.to_rgba();
+            c[3] = c[3] * alpha;
+            Color::from_rgba(c)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MessageLog;
+
+    #[test]
+    fn test_merge() {
+        let mut log = MessageLog::new(3);
+        log.add(1, "hello");
+        log.add(2, "hello");
+        assert_eq!(1, log.messages().len());
+        assert_eq!(2, log.messages()[0].count);
+        assert_eq!(2, log.messages()[0].turn);
+
+        // Only repeats of the newest message are merged.
+        log.add(3, "{red}hello");
+        log.add(4, "hello");
+        assert_eq!(3, log.messages().len());
+        assert_eq!(1, log.messages()[2].count);
+
+        // The oldest messages drop out.
+        log.add(5, "bye");
+        assert_eq!(3, log.messages().len());
+        assert_eq!(3, log.messages()[0].turn);
+    }
+
+    #[test]
+    fn test_scroll() {
+        use event::Event;
+        use key::Key;
+
+        let mut log = MessageLog::new(10);
+        log.scroll(1);
+        assert!(log.handle_event(&Event::KeyPressed(Key::PageUp)));
+        assert!(!log.is_scrolled());
+        log.add(1, "a");
+        assert!(!log.is_scrolled());
+
+        log.add(2, "b");
+        log.add(3, "c");
+        log.scroll(5);
+        assert_eq!(2, log.scroll);
+        log.scroll(-1);
+        assert_eq!(1, log.scroll);
+        // New messages don't move a scrolled back view.
+        log.add(4, "d");
+        assert_eq!(2, log.scroll);
+        log.scroll(-10);
+        assert!(!log.is_scrolled());
+        // Or pull up the view of the newest messages.
+        log.add(5, "e");
+        assert!(!log.is_scrolled());
+
+        log.scroll(2);
+        assert!(log.handle_event(&Event::KeyPressed(Key::End)));
+        assert!(!log.is_scrolled());
+        log.scroll(1);
+        log.clear();
+        assert!(!log.is_scrolled());
+        log.scroll(1);
+        assert!(!log.is_scrolled());
+    }
+}
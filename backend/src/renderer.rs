@@ -62,6 +62,13 @@ impl Renderer {
         }
     }
 
+    /// Replace the atlas texture after new images have been added to the
+    /// atlas.
+    pub fn set_atlas<'a, T>(&mut self, display: &glium::Display, texture_image: T)
+        where T: texture::Texture2dDataSource<'a> {
+        self.atlas = texture::Texture2d::new(display, texture_image);
+    }
+
     /// Draw sprites on target.
     fn draw_sprites<S>(&self, display: &glium::Display, target: &mut S,
                        vertices: Vec<Vertex>, indices: Vec<u16>)
@@ -0,0 +1,445 @@
+/*!
+Text terminal output for consoles.
+
+Draws a Console on a terminal with ANSI escape codes, for playing over a
+remote shell. The terminal needs to be in raw mode for the key input to
+come through unbuffered, see `set_raw_mode`.
+ */
+
+use std::io::{self, Write};
+use std::num::Float;
+use std::str;
+use time;
+use util::{V2, Rgb, Color};
+use console::{Console, Cell};
+use event::Event;
+use key::Key;
+
+/// How colors are sent to the terminal.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ColorMode {
+    /// The 256 color xterm palette, colors are matched to the nearest
+    /// palette color.
+    Palette256,
+    /// 24-bit color.
+    TrueColor,
+}
+
+/// Output backend that draws consoles on a terminal.
+pub struct Terminal<W> {
+    out: W,
+    mode: ColorMode,
+    size: V2<i32>,
+    /// The cells currently shown on the terminal, None if unknown.
+    screen: Vec<Option<Cell>>,
+}
+
+impl<W: Write> Terminal<W> {
+    pub fn new(out: W, mode: ColorMode) -> Terminal<W> {
+        Terminal {
+            out: out,
+            mode: mode,
+            size: V2(0, 0),
+            screen: Vec::new(),
+        }
+    }
+
+    /// Draw the console on the terminal. Only the cells that differ from
+    /// what was drawn on the previous update are written.
+    pub fn update(&mut self, console: &Console) -> io::Result<()> {
+        let mut buf = Vec::new();
+
+        if console.size() != self.size {
+            // Start over with a blank screen and a hidden cursor.
+            self.size = console.size();
+            let n = (self.size.0 * self.size.1) as usize;
+            self.screen = (0..n).map(|_| None).collect();
+            try!(write!(&mut buf, "\x1b[0m\x1b[2J\x1b[?25l"));
+        }
+
+        // Terminal state after the previous write, to skip redundant
+        // escape codes.
+        let mut cursor = None;
+        let mut fore = None;
+        let mut back = None;
+
+        for y in 0..self.size.1 {
+            for x in 0..self.size.0 {
+                let i = (x + y * self.size.0) as usize;
+                let cell = console.get(V2(x, y)).unwrap();
+                if self.screen[i] == Some(cell) { continue; }
+                self.screen[i] = Some(cell);
+
+                if cursor != Some(V2(x, y)) {
+                    try!(write!(&mut buf, "\x1b[{};{}H", y + 1, x + 1));
+                }
+                if fore != Some(cell.fore) {
+                    try!(write!(&mut buf, "{}", color_code(self.mode, cell.fore, true)));
+                    fore = Some(cell.fore);
+                }
+                if back != Some(cell.back) {
+                    try!(write!(&mut buf, "{}", color_code(self.mode, cell.back, false)));
+                    back = Some(cell.back);
+                }
+                let c = if (cell.c as u32) < 32 { ' ' } else { cell.c };
+                try!(write!(&mut buf, "{}", c));
+                // Wide chars may move the cursor more than one column, so
+                // only trust the position after ASCII.
+                cursor = if (c as u32) < 128 { Some(V2(x + 1, y)) } else { None };
+            }
+        }
+
+        if buf.len() > 0 {
+            try!(self.out.write_all(&buf[..]));
+            try!(self.out.flush());
+        }
+        Ok(())
+    }
+
+    /// Make the next update redraw the whole console, eg. after the
+    /// terminal has been cleared by something else.
+    pub fn invalidate(&mut self) {
+        self.size = V2(0, 0);
+    }
+
+    /// Reset the terminal colors, clear the screen and show the cursor
+    /// again.
+    pub fn reset(&mut self) -> io::Result<()> {
+        self.invalidate();
+        try!(write!(&mut self.out, "\x1b[0m\x1b[2J\x1b[H\x1b[?25h"));
+        self.out.flush()
+    }
+
+    /// Return the underlying writer.
+    pub fn into_inner(self) -> W { self.out }
+}
+
+fn color_code(mode: ColorMode, color: Rgb, fore: bool) -> String {
+    let layer = if fore { 38 } else { 48 };
+    match mode {
+        ColorMode::TrueColor => format!("\x1b[{};2;{};{};{}m", layer, color.r, color.g, color.b),
+        ColorMode::Palette256 => format!("\x1b[{};5;{}m", layer, xterm_color(&color)),
+    }
+}
+
+/// Return the xterm 256 color palette index closest to a color. Only the
+/// color cube and the grayscale ramp are used, since terminals differ in
+/// how they show the first 16 colors.
+pub fn xterm_color<C: Color>(color: &C) -> u8 {
+    static LEVELS: [f32; 6] = [0.0, 95.0, 135.0, 175.0, 215.0, 255.0];
+
+    let rgba = color.to_rgba();
+    let c = [rgba[0] * 255.0, rgba[1] * 255.0, rgba[2] * 255.0];
+
+    let mut cube = [0; 3];
+    for i in 0..3 {
+        for j in 1..6 {
+            if (c[i] - LEVELS[j]).abs() < (c[i] - LEVELS[cube[i]]).abs() { cube[i] = j; }
+        }
+    }
+    let cube_dist = dist(c, [LEVELS[cube[0]], LEVELS[cube[1]], LEVELS[cube[2]]]);
+
+    // Grays 232 to 255 go from 8 to 238 in steps of 10.
+    let avg = (c[0] + c[1] + c[2]) / 3.0;
+    let gray = ((avg - 8.0) / 10.0).round().max(0.0).min(23.0);
+    let level = 8.0 + gray * 10.0;
+    let gray_dist = dist(c, [level, level, level]);
+
+    return if gray_dist < cube_dist {
+        232 + gray as u8
+    } else {
+        (16 + cube[0] * 36 + cube[1] * 6 + cube[2]) as u8
+    };
+
+    fn dist(a: [f32; 3], b: [f32; 3]) -> f32 {
+        (a[0] - b[0]) * (a[0] - b[0]) + (a[1] - b[1]) * (a[1] - b[1]) + (a[2] - b[2]) * (a[2] - b[2])
+    }
+}
+
+/// How long a lone escape byte waits for the rest of an escape sequence
+/// before it's taken to be the escape key.
+const ESCAPE_TIMEOUT_S: f64 = 0.05;
+
+/// Translates terminal input bytes into key and char events.
+///
+/// Terminals don't report key releases, so only KeyPressed events are
+/// generated. Printable chars generate both a KeyPressed event, if there is
+/// a matching key, and a Char event like the windowed backend.
+///
+/// The escape key sends the same byte that starts escape sequences, and a
+/// sequence may be split across reads. An escape byte at the end of the
+/// input is kept pending until the next read or until it times out, see
+/// `flush`.
+pub struct InputParser {
+    /// Bytes of an incomplete escape sequence or UTF-8 char.
+    buf: Vec<u8>,
+    /// When a lone escape byte started waiting in the buffer.
+    escape_time: Option<f64>,
+}
+
+impl InputParser {
+    pub fn new() -> InputParser {
+        InputParser { buf: Vec::new(), escape_time: None }
+    }
+
+    /// Parse input read from the terminal. Incomplete input at the end is
+    /// kept until the next call.
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<Event<'static>> {
+        self.feed_at(bytes, time::precise_time_s())
+    }
+
+    /// Report a pending escape byte as the escape key if no more input has
+    /// followed it in a short while. Call this when a read returns nothing,
+    /// eg. from a read with a timeout.
+    pub fn flush(&mut self) -> Vec<Event<'static>> {
+        self.flush_at(time::precise_time_s())
+    }
+
+    fn feed_at(&mut self, bytes: &[u8], now: f64) -> Vec<Event<'static>> {
+        // Input arriving after the timeout doesn't continue the sequence.
+        let mut ret = self.flush_at(now);
+        self.buf.push_all(bytes);
+        let mut pos = 0;
+
+        while pos < self.buf.len() {
+            let consumed = parse(&self.buf[pos..], &mut ret);
+            if consumed == 0 { break; }
+            pos += consumed;
+        }
+
+        self.buf = self.buf[pos..].to_vec();
+        self.escape_time = if self.buf == [0x1b] { self.escape_time.or(Some(now)) } else { None };
+        ret
+    }
+
+    fn flush_at(&mut self, now: f64) -> Vec<Event<'static>> {
+        match self.escape_time {
+            Some(t) if now - t >= ESCAPE_TIMEOUT_S => {
+                self.buf.clear();
+                self.escape_time = None;
+                vec![Event::KeyPressed(Key::Escape)]
+            }
+            _ => Vec::new()
+        }
+    }
+}
+
+/// Parse one key from the start of input into events. Return the number of
+/// bytes used, or 0 if the input is incomplete.
+fn parse(input: &[u8], events: &mut Vec<Event<'static>>) -> usize {
+    match input[0] {
+        0x1b => {
+            // Escape key or the start of a sequence, can't tell yet.
+            if input.len() == 1 { return 0; }
+            if input[1] != b'[' && input[1] != b'O' {
+                // Not a sequence, the escape key was pressed before
+                // another key.
+                events.push(Event::KeyPressed(Key::Escape));
+                return 1;
+            }
+            // CSI and SS3 sequences end in a letter or a tilde.
+            let end = match input[2..].iter().position(|&b| (b as char).is_alphabetic() || b == b'~') {
+                Some(i) => i + 2,
+                None => { return 0; }
+            };
+            if let Some(key) = sequence_key(&input[2..end + 1]) {
+                events.push(Event::KeyPressed(key));
+            }
+            end + 1
+        }
+        b'\r' | b'\n' => { events.push(Event::KeyPressed(Key::Enter)); 1 }
+        b'\t' => { events.push(Event::KeyPressed(Key::Tab)); 1 }
+        0x7f | 0x08 => { events.push(Event::KeyPressed(Key::Backspace)); 1 }
+        b if b < 32 => 1,
+        b => {
+            // Only real lead bytes start multi-byte chars.
+            let len = match b {
+                0xc2...0xdf => 2,
+                0xe0...0xef => 3,
+                0xf0...0xf4 => 4,
+                _ => 1
+            };
+            // Skip a single bad byte, so that the input after it still
+            // comes through.
+            if input[1..].iter().take(len - 1).any(|&c| c & 0xc0 != 0x80) { return 1; }
+            if input.len() < len { return 0; }
+            match str::from_utf8(&input[..len]) {
+                Ok(s) => {
+                    let c = s.chars().next().unwrap();
+                    if let Some(key) = char_key(c) { events.push(Event::KeyPressed(key)); }
+                    events.push(Event::Char(c));
+                    len
+                }
+                Err(_) => 1
+            }
+        }
+    }
+}
+
+/// Key for the part of an escape sequence after the "ESC [" or "ESC O".
+fn sequence_key(seq: &[u8]) -> Option<Key> {
+    let seq = match str::from_utf8(seq) { Ok(s) => s, Err(_) => { return None; } };
+    match seq {
+        "A" => Some(Key::Up),
+        "B" => Some(Key::Down),
+        "C" => Some(Key::Right),
+        "D" => Some(Key::Left),
+        "H" | "1~" | "7~" => Some(Key::Home),
+        "F" | "4~" | "8~" => Some(Key::End),
+        "2~" => Some(Key::Insert),
+        "3~" => Some(Key::Delete),
+        "5~" => Some(Key::PageUp),
+        "6~" => Some(Key::PageDown),
+        "P" | "11~" => Some(Key::F1),
+        "Q" | "12~" => Some(Key::F2),
+        "R" | "13~" => Some(Key::F3),
+        "S" | "14~" => Some(Key::F4),
+        "15~" => Some(Key::F5),
+        "17~" => Some(Key::F6),
+        "18~" => Some(Key::F7),
+        "19~" => Some(Key::F8),
+        "20~" => Some(Key::F9),
+        "21~" => Some(Key::F10),
+        "23~" => Some(Key::F11),
+        "24~" => Some(Key::F12),
+        _ => None
+    }
+}
+
+/// Key that types a printable char.
+fn char_key(c: char) -> Option<Key> {
+    static LETTERS: [Key; 26] = [
+        Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I,
+        Key::J, Key::K, Key::L, Key::M, Key::N, Key::O, Key::P, Key::Q, Key::R,
+        Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z];
+    static DIGITS: [Key; 10] = [
+        Key::Num0, Key::Num1, Key::Num2, Key::Num3, Key::Num4,
+        Key::Num5, Key::Num6, Key::Num7, Key::Num8, Key::Num9];
+
+    match c {
+        'a'...'z' => Some(LETTERS[(c as u32 - 'a' as u32) as usize]),
+        'A'...'Z' => Some(LETTERS[(c as u32 - 'A' as u32) as usize]),
+        '0'...'9' => Some(DIGITS[(c as u32 - '0' as u32) as usize]),
+        ' ' => Some(Key::Space),
+        '\'' => Some(Key::Apostrophe),
+        ',' => Some(Key::Comma),
+        '-' => Some(Key::Minus),
+        '.' => Some(Key::Period),
+        '/' => Some(Key::Slash),
+        ';' => Some(Key::Semicolon),
+        '=' => Some(Key::Equals),
+        '[' => Some(Key::LeftBracket),
+        '\\' => Some(Key::Backslash),
+        ']' => Some(Key::RightBracket),
+        '`' => Some(Key::Grave),
+        _ => None
+    }
+}
+
+/// Switch the controlling terminal in or out of raw mode with stty. Return
+/// whether stty succeeded.
+#[cfg(unix)]
+pub fn set_raw_mode(raw: bool) -> io::Result<bool> {
+    use std::process::{Command, Stdio};
+    let args: &[&str] = if raw { &["raw", "-echo"] } else { &["sane"] };
+    let status = try!(Command::new("stty").args(args).stdin(Stdio::inherit()).status());
+    Ok(status.success())
+}
+
+#[cfg(test)]
+mod test {
+    use event::Event;
+    use key::Key;
+
+    fn describe(events: Vec<Event<'static>>) -> Vec<String> {
+        events.into_iter().map(|e| match e {
+            Event::KeyPressed(k) => format!("{:?}", k),
+            Event::Char(c) => format!("{:?}", c),
+            _ => "other".to_string()
+        }).collect()
+    }
+
+    #[test]
+    fn test_input() {
+        use super::InputParser;
+
+        let mut parser = InputParser::new();
+        assert_eq!(vec!["A", "'a'", "Up", "PageDown", "Enter", "'ä'"],
+                   describe(parser.feed(b"a\x1b[A\x1b[6~\r\xc3\xa4")));
+        assert_eq!(vec!["F1"], describe(parser.feed(b"\x1bOP")));
+        // Split sequences continue on the next feed.
+        assert!(parser.feed(b"\x1b[2").is_empty());
+        assert_eq!(vec!["Insert"], describe(parser.feed(b"~")));
+        assert!(parser.feed(b"\xc3").is_empty());
+        assert_eq!(vec!["'ö'"], describe(parser.feed(b"\xb6")));
+
+        // Bytes that aren't valid UTF-8 are skipped one at a time.
+        assert_eq!(vec!["A", "'a'"], describe(parser.feed(b"\x80a")));
+        assert_eq!(vec!["B", "'b'"], describe(parser.feed(b"\xff\xc3b")));
+        assert_eq!(vec!["C", "'c'"], describe(parser.feed(b"\xe0\x80c")));
+        assert!(parser.feed(b"\xbf").is_empty());
+        assert_eq!(vec!["D", "'d'"], describe(parser.feed(b"d")));
+    }
+
+    #[test]
+    fn test_escape() {
+        use super::InputParser;
+
+        let esc = format!("{:?}", Key::Escape);
+        let mut parser = InputParser::new();
+        // A lone escape byte waits for the rest of the sequence.
+        assert_eq!(vec!["A", "'a'"], describe(parser.feed_at(b"a\x1b", 10.0)));
+        assert!(parser.flush_at(10.01).is_empty());
+        assert_eq!(vec!["Up"], describe(parser.feed_at(b"[A", 10.02)));
+
+        // Or turns into the escape key when nothing follows.
+        assert!(parser.feed_at(b"\x1b", 11.0).is_empty());
+        assert!(parser.feed_at(b"", 11.01).is_empty());
+        assert_eq!(vec![esc.clone()], describe(parser.flush_at(11.1)));
+        assert!(parser.flush_at(12.0).is_empty());
+
+        // An escape byte that doesn't start a sequence is the escape key.
+        assert_eq!(vec![esc.clone()], describe(parser.feed_at(b"\x1b\x1b", 12.5)));
+        assert_eq!(vec![esc.clone(), "Q".to_string(), "'q'".to_string()],
+                   describe(parser.feed_at(b"q", 12.51)));
+
+        // Input after the timeout isn't part of the sequence.
+        assert!(parser.feed_at(b"\x1b", 13.0).is_empty());
+        assert_eq!(vec![esc.clone(), "X".to_string(), "'x'".to_string()],
+                   describe(parser.feed_at(b"x", 13.5)));
+    }
+
+    #[test]
+    fn test_xterm_color() {
+        use super::xterm_color;
+        use util::Rgb;
+
+        assert_eq!(16, xterm_color(&Rgb::new(0, 0, 0)));
+        assert_eq!(196, xterm_color(&Rgb::new(255, 0, 0)));
+        assert_eq!(231, xterm_color(&Rgb::new(255, 255, 255)));
+        assert_eq!(244, xterm_color(&Rgb::new(128, 128, 128)));
+        assert_eq!(67, xterm_color(&Rgb::new(90, 130, 170)));
+    }
+
+    #[test]
+    fn test_update() {
+        use super::{Terminal, ColorMode};
+        use console::Console;
+        use util::{V2, Rgb};
+
+        let mut console = Console::new(2, 1);
+        console.fore = Rgb::new(1, 2, 3);
+        console.back = Rgb::new(0, 0, 0);
+        console.print(V2(0, 0), "ab");
+        let mut term = Terminal::new(Vec::new(), ColorMode::TrueColor);
+        term.update(&console).unwrap();
+        term.update(&console).unwrap();
+        console.put_char(V2(1, 0), 'c');
+        term.update(&console).unwrap();
+
+        let out = String::from_utf8(term.into_inner()).unwrap();
+        assert_eq!("\x1b[0m\x1b[2J\x1b[?25l\
+                    \x1b[1;1H\x1b[38;2;1;2;3m\x1b[48;2;0;0;0mab\
+                    \x1b[1;2H\x1b[38;2;1;2;3m\x1b[48;2;0;0;0mc", out);
+    }
+}
@@ -0,0 +1,72 @@
+use std::num::Float;
+use util::{V2, Rect, Rgba, color};
+use util::tilemap::TileMap;
+use canvas::{Canvas, Image};
+use canvas_util::CanvasUtil;
+
+/// Draws the part of a tile map that is visible through a camera.
+#[derive(Copy, Clone, Debug)]
+pub struct TileRenderer {
+    /// Size of a tile in pixels.
+    pub tile_size: V2<f32>,
+    /// Map position in pixels that is shown at the top left corner of the
+    /// viewport.
+    pub camera: V2<f32>,
+    /// Screen area to draw the map in.
+    pub viewport: Rect<f32>,
+    /// Layer to draw the bottom map layer in. Each map layer above it is
+    /// drawn slightly closer.
+    pub z: f32,
+}
+
+impl TileRenderer {
+    pub fn new(tile_size: V2<f32>, viewport: Rect<f32>) -> TileRenderer {
+        TileRenderer {
+            tile_size: tile_size,
+            camera: V2(0.0, 0.0),
+            viewport: viewport,
+            z: 0.5,
+        }
+    }
+
+    /// Center the camera on the center of a tile.
+    pub fn center_on(&mut self, tile: V2<i32>) {
+        let center = V2((tile.0 as f32 + 0.5) * self.tile_size.0, (tile.1 as f32 + 0.5) * self.tile_size.1);
+        self.camera = center - self.viewport.dim() / 2.0;
+    }
+
+    /// Screen position of the top left corner of a tile.
+    pub fn screen_pos(&self, tile: V2<i32>) -> V2<f32> {
+        self.viewport.mn() - self.camera + V2(tile.0 as f32 * self.tile_size.0, tile.1 as f32 * self.tile_size.1)
+    }
+
+    /// The tile under a screen position, eg. for mouse picking.
+    pub fn tile_at(&self, screen_pos: V2<f32>) -> V2<i32> {
+        let p = screen_pos - self.viewport.mn() + self.camera;
+        V2((p.0 / self.tile_size.0).floor() as i32, (p.1 / self.tile_size.1).floor() as i32)
+    }
+
+    /// The area of tiles that are at least partially in the viewport.
+    pub fn visible_area(&self) -> Rect<i32> {
+        let mn = self.tile_at(self.viewport.mn());
+        let mx = self.viewport.mx() - self.viewport.mn() + self.camera;
+        let mx = V2((mx.0 / self.tile_size.0).ceil() as i32, (mx.1 / self.tile_size.1).ceil() as i32);
+        Rect(mn, mx - mn)
+    }
+
+    /// Draw the visible part of the map. The image and tint color for each
+    /// tile are given by f, which receives the layer index, position and
+    /// value of the tile. Tiles for which f returns None are not drawn.
+    pub fn draw<T, F>(&self, canvas: &mut Canvas, map: &TileMap<T>, f: F)
+        where F: Fn(usize, V2<i32>, &T) -> Option<(Image, Rgba)> {
+        let area = self.visible_area();
+        for (i, layer) in map.layers().iter().enumerate() {
+            let z = self.z - i as f32 * 0.001;
+            for (pos, tile) in layer.region(&area) {
+                if let Some((img, tint)) = f(i, pos, tile) {
+                    canvas.draw_image(img, self.screen_pos(pos), z, &tint, &color::BLACK);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Compare the hash map and the grid Dijkstra maps on a 200x200 map.
+
+#![feature(test)]
+
+extern crate test;
+extern crate calx_util;
+
+use test::Bencher;
+use calx_util::{V2, Rect, Dijkstra, DijkstraNode, GridDijkstra};
+
+static DIRS: [V2<i32>; 4] = [V2(0, -1), V2(1, 0), V2(0, 1), V2(-1, 0)];
+
+const SIZE: i32 = 200;
+
+/// A map with regularly spaced wall segments.
+fn is_wall(p: V2<i32>) -> bool {
+    p.0 % 10 == 5 && p.1 % 20 < 15 || p.1 % 10 == 5 && p.0 % 20 > 5
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+struct Cell(V2<i32>);
+
+impl DijkstraNode for Cell {
+    fn neighbors(&self) -> Vec<Cell> {
+        let Cell(p) = *self;
+        DIRS.iter().map(|&d| Cell(p + d)).collect()
+    }
+}
+
+fn area() -> Rect<i32> { Rect(V2(0, 0), V2(SIZE, SIZE)) }
+
+#[bench]
+fn bench_hash_dijkstra(b: &mut Bencher) {
+    let area = area();
+    b.iter(|| {
+        Dijkstra::new(vec![Cell(V2(SIZE / 2, SIZE / 2))],
+                      |c| area.contains(&c.0) && !is_wall(c.0), 10000)
+    });
+}
+
+#[bench]
+fn bench_grid_dijkstra(b: &mut Bencher) {
+    b.iter(|| {
+        GridDijkstra::new(area(), &DIRS, &[V2(SIZE / 2, SIZE / 2)],
+                          |p| if is_wall(p) { None } else { Some(1) }, 10000)
+    });
+}
+
+#[bench]
+fn bench_grid_dijkstra_update(b: &mut Bencher) {
+    let mut map = GridDijkstra::new(area(), &DIRS, &[V2(SIZE / 2, SIZE / 2)],
+                                    |p| if is_wall(p) { None } else { Some(1) }, 10000);
+    // A door that keeps opening and closing.
+    b.iter(|| {
+        map.set_cost(V2(SIZE / 2 + 5, SIZE / 2 + 2), Some(1));
+        map.set_cost(V2(SIZE / 2 + 5, SIZE / 2 + 2), None);
+    });
+}
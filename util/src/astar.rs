@@ -0,0 +1,200 @@
+use std::hash::Hash;
+use std::cmp::{max, min, Ordering};
+use std::collections::{HashMap, HashSet, BinaryHeap};
+use geom::V2;
+use dijkstra::DijkstraNode;
+
+/// Find the lowest cost path from start to goal with A* search.
+///
+/// The heuristic estimates the remaining cost from a node to the goal. It
+/// must never overestimate the cost for the path to be the shortest one.
+/// Nodes for which is_valid returns false are not entered. The search gives
+/// up after expanding max_nodes nodes.
+///
+/// Returns the path including the start and goal nodes and its total cost,
+/// or None if no path was found.
+pub fn astar<N, H, F>(start: N, goal: N, heuristic: H, is_valid: F, max_nodes: usize)
+    -> Option<(Vec<N>, u32)>
+    where N: DijkstraNode, H: Fn(&N, &N) -> u32, F: Fn(&N) -> bool {
+    search(start, goal, heuristic, |n, _| {
+        n.neighbors().into_iter()
+            .filter(|m| is_valid(m))
+            .map(|m| { let c = n.cost(&m); (m, c) })
+            .collect()
+    }, max_nodes)
+}
+
+/// A* search over a graph given by a successor function. The function
+/// returns the nodes reachable from a node with the costs of getting there,
+/// and also gets the node's predecessor on the best path found so far.
+pub fn search<N, H, S>(start: N, goal: N, heuristic: H, successors: S, max_nodes: usize)
+    -> Option<(Vec<N>, u32)>
+    where N: Eq+Hash+Clone, H: Fn(&N, &N) -> u32, S: Fn(&N, Option<&N>) -> Vec<(N, u32)> {
+    let mut open = BinaryHeap::new();
+    let mut closed = HashSet::new();
+    let mut costs = HashMap::new();
+    let mut came_from = HashMap::new();
+
+    let h = heuristic(&start, &goal);
+    costs.insert(start.clone(), 0);
+    open.push(Open { estimate: h, remaining: h, node: start });
+
+    while let Some(Open { node, .. }) = open.pop() {
+        let cost = *costs.get(&node).unwrap();
+        if node == goal {
+            return Some((trace_path(&came_from, node), cost));
+        }
+        if closed.contains(&node) { continue; }
+        if closed.len() >= max_nodes { return None; }
+        closed.insert(node.clone());
+
+        let next = successors(&node, came_from.get(&node));
+        for (m, step) in next.into_iter() {
+            if closed.contains(&m) { continue; }
+            let new_cost = cost + step;
+            let improved = costs.get(&m).map_or(true, |&c| new_cost < c);
+            if improved {
+                let h = heuristic(&m, &goal);
+                costs.insert(m.clone(), new_cost);
+                came_from.insert(m.clone(), node.clone());
+                open.push(Open { estimate: new_cost + h, remaining: h, node: m });
+            }
+        }
+    }
+    None
+}
+
+/// Walk the came_from links back from the end node and return the path
+/// from the start node to it.
+fn trace_path<N: Eq+Hash+Clone>(came_from: &HashMap<N, N>, end: N) -> Vec<N> {
+    let mut ret = vec![end.clone()];
+    let mut node = end;
+    loop {
+        let prev = match came_from.get(&node) {
+            Some(n) => n.clone(),
+            None => break
+        };
+        ret.push(prev.clone());
+        node = prev;
+    }
+    ret.reverse();
+    ret
+}
+
+/// Open set entry for A*. Pops the lowest estimated total cost first. Ties
+/// are broken in favor of the node closest to the goal, which makes the
+/// search follow one straight line instead of fanning out over all the
+/// equally good paths.
+struct Open<N> {
+    estimate: u32,
+    remaining: u32,
+    node: N,
+}
+
+impl<N> PartialEq for Open<N> {
+    fn eq(&self, other: &Open<N>) -> bool {
+        self.estimate == other.estimate && self.remaining == other.remaining
+    }
+}
+
+impl<N> Eq for Open<N> {}
+
+impl<N> PartialOrd for Open<N> {
+    fn partial_cmp(&self, other: &Open<N>) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl<N> Ord for Open<N> {
+    fn cmp(&self, other: &Open<N>) -> Ordering {
+        (other.estimate, other.remaining).cmp(&(self.estimate, self.remaining))
+    }
+}
+
+/// Distance heuristic for 4-connected grids with unit step cost.
+pub fn manhattan(a: &V2<i32>, b: &V2<i32>) -> u32 {
+    ((a.0 - b.0).abs() + (a.1 - b.1).abs()) as u32
+}
+
+/// Distance heuristic for 8-connected grids where diagonal steps cost the
+/// same as orthogonal ones.
+pub fn chebyshev(a: &V2<i32>, b: &V2<i32>) -> u32 {
+    max((a.0 - b.0).abs(), (a.1 - b.1).abs()) as u32
+}
+
+/// Distance heuristic for 8-connected grids where orthogonal steps cost 10
+/// and diagonal steps cost 14, an integer approximation of the real
+/// diagonal length.
+pub fn octile(a: &V2<i32>, b: &V2<i32>) -> u32 {
+    let (dx, dy) = ((a.0 - b.0).abs(), (a.1 - b.1).abs());
+    (10 * max(dx, dy) + 4 * min(dx, dy)) as u32
+}
+
+#[cfg(test)]
+mod test {
+    use geom::V2;
+    use dijkstra::DijkstraNode;
+
+    static MAP: [&'static str; 5] = [
+        "......",
+        ".####.",
+        "....#.",
+        ".##.#.",
+        "......",
+    ];
+
+    fn is_open(p: &V2<i32>) -> bool {
+        p.0 >= 0 && p.1 >= 0 && p.0 < 6 && p.1 < 5 && MAP[p.1 as usize].as_bytes()[p.0 as usize] == b'.'
+    }
+
+    #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+    struct Ortho(V2<i32>);
+
+    impl DijkstraNode for Ortho {
+        fn neighbors(&self) -> Vec<Ortho> {
+            let Ortho(p) = *self;
+            [V2(0, -1), V2(1, 0), V2(0, 1), V2(-1, 0)].iter().map(|&d| Ortho(p + d)).collect()
+        }
+    }
+
+    #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+    struct Diagonal(V2<i32>);
+
+    impl DijkstraNode for Diagonal {
+        fn neighbors(&self) -> Vec<Diagonal> {
+            let Diagonal(p) = *self;
+            [V2(-1, -1), V2(0, -1), V2(1, -1), V2(-1, 0), V2(1, 0), V2(-1, 1), V2(0, 1), V2(1, 1)]
+                .iter().map(|&d| Diagonal(p + d)).collect()
+        }
+
+        fn cost(&self, other: &Diagonal) -> u32 {
+            if (self.0).0 != (other.0).0 && (self.0).1 != (other.0).1 { 14 } else { 10 }
+        }
+    }
+
+    #[test]
+    fn test_astar() {
+        use super::{astar, manhattan, octile};
+
+        let h = |a: &Ortho, b: &Ortho| manhattan(&a.0, &b.0);
+        let valid = |n: &Ortho| is_open(&n.0);
+
+        let (path, cost) = astar(Ortho(V2(0, 2)), Ortho(V2(3, 2)), &h, &valid, 100).unwrap();
+        assert_eq!(3, cost);
+        assert_eq!(vec![V2(0, 2), V2(1, 2), V2(2, 2), V2(3, 2)],
+                   path.iter().map(|n| n.0).collect::<Vec<V2<i32>>>());
+
+        // Has to go around the wall.
+        let (path, cost) = astar(Ortho(V2(0, 2)), Ortho(V2(5, 2)), &h, &valid, 100).unwrap();
+        assert_eq!(9, cost);
+        assert_eq!(10, path.len());
+        assert!(path.iter().all(|n| is_open(&n.0)));
+
+        assert_eq!(None, astar(Ortho(V2(0, 2)), Ortho(V2(2, 1)), &h, &valid, 100));
+        assert_eq!(None, astar(Ortho(V2(0, 2)), Ortho(V2(5, 2)), &h, &valid, 5));
+
+        let (path, cost) = astar(Diagonal(V2(0, 0)), Diagonal(V2(5, 2)),
+                                 |a, b| octile(&a.0, &b.0), |n| is_open(&n.0), 100).unwrap();
+        // The wall blocks the early diagonal steps.
+        assert_eq!(5 * 10 + 14, cost);
+        assert_eq!(Diagonal(V2(5, 1)), path[5]);
+    }
+}
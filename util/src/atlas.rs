@@ -10,6 +10,7 @@ use primitive::Primitive;
 pub struct AtlasBuilder {
     images: Vec<ImageBuffer<Rgba<u8>, Vec<u8>>>,
     draw_offsets: Vec<V2<i32>>,
+    reserved_area: i32,
 }
 
 impl AtlasBuilder {
@@ -17,25 +18,30 @@ impl AtlasBuilder {
         AtlasBuilder {
             images: vec![],
             draw_offsets: vec![],
+            reserved_area: 0,
         }
     }
 
     pub fn push<P: Pixel<Subpixel=u8> + 'static, I: GenericImage<Pixel=P>>(
         &mut self, offset: V2<i32>, image: &I) -> usize {
-        let Rect(pos, dim) = img::crop_alpha(image);
-        let image = ImageBuffer::from_fn(
-            dim.0 as u32, dim.1 as u32,
-            |x, y| image.get_pixel(
-                pos.0 as u32 + x, pos.1 as u32 + y).to_rgba());
+        let (image, pos) = crop(image);
         self.images.push(image);
         self.draw_offsets.push(pos + offset);
         self.images.len() - 1
     }
+
+    /// Reserve extra free space in pixels in the atlas for images that get
+    /// added with Atlas::insert after the atlas has been built.
+    pub fn reserve(&mut self, area: i32) {
+        self.reserved_area = self.reserved_area + area;
+    }
 }
 
 pub struct Atlas {
     pub image: ImageBuffer<Rgba<u8>, Vec<u8>>,
     pub items: Vec<AtlasItem>,
+    /// Free space remaining in the atlas image.
+    slots: Vec<Rect<i32>>,
 }
 
 /// One image stored in a texture atlas
@@ -60,16 +66,22 @@ impl Atlas {
             .collect();
 
         // Guesstimate the size for the atlas container.
-        let total_area = dims.iter().map(|dim| dim.0 * dim.1).fold(0, |a, b| a + b);
+        let total_area = dims.iter().map(|dim| dim.0 * dim.1).fold(0, |a, b| a + b)
+            + builder.reserved_area;
         let mut d = ((total_area as f64).sqrt() as u32).next_power_of_two();
         let mut offsets;
+        let mut slots;
 
         loop {
             assert!(d < 1000000000); // Sanity check
             match pack_rectangles(V2(d as i32, d as i32), &expanded_dims) {
-                Some(ret) => {
-                    offsets = ret;
-                    break;
+                Some((ret, free)) => {
+                    if area(&free) >= builder.reserved_area {
+                        offsets = ret;
+                        slots = free;
+                        break;
+                    }
+                    d = d * 2;
                 }
                 None => {
                     d = d * 2;
@@ -97,21 +109,62 @@ impl Atlas {
         return Atlas {
             image: image,
             items: items,
+            slots: slots,
         };
 
-        fn scale_vec(pixel_vec: V2<i32>, image_dim: V2<u32>) -> V2<f32> {
-            V2(pixel_vec.0 as f32 / image_dim.0 as f32,
-              pixel_vec.1 as f32 / image_dim.1 as f32)
+        fn area(slots: &Vec<Rect<i32>>) -> i32 {
+            slots.iter().map(|r| r.area()).fold(0, |a, b| a + b)
         }
     }
+
+    /// Add a new image into the free space of an already built atlas.
+    /// Return the index of the new item or None if the image doesn't fit in
+    /// the atlas.
+    pub fn insert<P: Pixel<Subpixel=u8> + 'static, I: GenericImage<Pixel=P>>(
+        &mut self, offset: V2<i32>, image: &I) -> Option<usize> {
+        let (image, pos) = crop(image);
+        let (w, h) = image.dimensions();
+        let dim = V2(w as i32, h as i32);
+        let atlas_pos = match place(dim + V2(1, 1), &mut self.slots) {
+            Some(p) => p,
+            None => { return None; }
+        };
+
+        img::blit(&image, &mut self.image, atlas_pos);
+        let (aw, ah) = self.image.dimensions();
+        let image_dim = V2(aw, ah);
+        self.items.push(AtlasItem {
+            pos: Rect((pos + offset).map(|x| x as f32), dim.map(|x| x as f32)),
+            tex: Rect(scale_vec(atlas_pos, image_dim), scale_vec(dim, image_dim))
+        });
+        Some(self.items.len() - 1)
+    }
+}
+
+fn scale_vec(pixel_vec: V2<i32>, image_dim: V2<u32>) -> V2<f32> {
+    V2(pixel_vec.0 as f32 / image_dim.0 as f32,
+      pixel_vec.1 as f32 / image_dim.1 as f32)
+}
+
+/// Crop the transparent edges from an image. Return the cropped image and
+/// its offset in the original image.
+fn crop<P: Pixel<Subpixel=u8> + 'static, I: GenericImage<Pixel=P>>(
+    image: &I) -> (ImageBuffer<Rgba<u8>, Vec<u8>>, V2<i32>) {
+    let Rect(pos, dim) = img::crop_alpha(image);
+    let ret = ImageBuffer::from_fn(
+        dim.0 as u32, dim.1 as u32,
+        |x, y| image.get_pixel(
+            pos.0 as u32 + x, pos.1 as u32 + y).to_rgba());
+    (ret, pos)
 }
 
 /// Try to pack several small rectangles into one large rectangle. Return
-/// offsets for the subrectangles within the container if a packing was found.
+/// offsets for the subrectangles within the container and the remaining free
+/// space if a packing was found.
 fn pack_rectangles<T: Primitive+Ord+Clone>(
     container_dim: V2<T>,
     dims: &Vec<V2<T>>)
-    -> Option<Vec<V2<T>>> {
+    -> Option<(Vec<V2<T>>, Vec<Rect<T>>)> {
     let init: T = NumCast::from(0i32).unwrap();
     let total_area = dims.iter().map(|dim| dim.0 * dim.1).fold(init, |a, b| a + b);
 
@@ -135,63 +188,89 @@ fn pack_rectangles<T: Primitive+Ord+Clone>(
         }
     }
 
-    return Some(ret);
-
-    ////////////////////////////////////////////////////////////////////////
-
-    /// Find the smallest slot in the slot vector that will fit the given
-    /// item.
-    fn place<T: Primitive+Ord>(
-        dim: V2<T>, slots: &mut Vec<Rect<T>>) -> Option<V2<T>> {
-        for i in 0..(slots.len()) {
-            let Rect(slot_pos, slot_dim) = slots[i];
-            if fits(dim, slot_dim) {
-                // Remove the original slot, it gets the item. Add the two new
-                // rectangles that form around the item.
-                let (new_1, new_2) = remaining_rects(dim, Rect(slot_pos, slot_dim));
-                slots.swap_remove(i);
-                slots.push(new_1);
-                slots.push(new_2);
-                // Sort by area from smallest to largest.
-                slots.sort_by(|&a, &b| a.area().cmp(&b.area()));
-                return Some(slot_pos);
-            }
+    Some((ret, slots))
+}
+
+/// Find the smallest slot in the slot vector that will fit the given item.
+/// Update the slot vector to remove the space taken by the item.
+fn place<T: Primitive+Ord>(
+    dim: V2<T>, slots: &mut Vec<Rect<T>>) -> Option<V2<T>> {
+    for i in 0..(slots.len()) {
+        let Rect(slot_pos, slot_dim) = slots[i];
+        if fits(dim, slot_dim) {
+            // Remove the original slot, it gets the item. Add the two new
+            // rectangles that form around the item.
+            let (new_1, new_2) = remaining_rects(dim, Rect(slot_pos, slot_dim));
+            slots.swap_remove(i);
+            slots.push(new_1);
+            slots.push(new_2);
+            // Sort by area from smallest to largest.
+            slots.sort_by(|&a, &b| a.area().cmp(&b.area()));
+            return Some(slot_pos);
         }
-        None
     }
+    None
+}
 
-    /// Return the two remaining parts of container rect when the dim-sized
-    /// item is placed in the top left corner.
-    fn remaining_rects<T: Primitive+Ord>(
-        dim: V2<T>, Rect(rect_pos, rect_dim): Rect<T>) ->
-        (Rect<T>, Rect<T>) {
-        assert!(fits(dim, rect_dim));
-
-        // Choose between making a vertical or a horizontal split
-        // based on which leaves a bigger open rectangle.
-        let vert_vol = max(rect_dim.0 * (rect_dim.1 - dim.1),
-            (rect_dim.0 - dim.0) * dim.1);
-        let horiz_vol = max(dim.0 * (rect_dim.1 - dim.1),
-            (rect_dim.0 - dim.0) * rect_dim.1);
-
-        if vert_vol > horiz_vol {
-            //     |AA
-            // ----+--
-            // BBBBBBB
-            // BBBBBBB
-            (Rect(V2(rect_pos.0 + dim.0, rect_pos.1), V2(rect_dim.0 - dim.0, dim.1)),
-             Rect(V2(rect_pos.0, rect_pos.1 + dim.1), V2(rect_dim.0, rect_dim.1 - dim.1)))
-        } else {
-            //     |BB
-            // ----+BB
-            // AAAA|BB
-            // AAAA|BB
-            (Rect(V2(rect_pos.0, rect_pos.1 + dim.1), V2(dim.0, rect_dim.1 - dim.1)),
-             Rect(V2(rect_pos.0 + dim.0, rect_pos.1), V2(rect_dim.0 - dim.0, rect_dim.1)))
-        }
+/// Return the two remaining parts of container rect when the dim-sized item
+/// is placed in the top left corner.
+fn remaining_rects<T: Primitive+Ord>(
+    dim: V2<T>, Rect(rect_pos, rect_dim): Rect<T>) ->
+    (Rect<T>, Rect<T>) {
+    assert!(fits(dim, rect_dim));
+
+    // Choose between making a vertical or a horizontal split
+    // based on which leaves a bigger open rectangle.
+    let vert_vol = max(rect_dim.0 * (rect_dim.1 - dim.1),
+        (rect_dim.0 - dim.0) * dim.1);
+    let horiz_vol = max(dim.0 * (rect_dim.1 - dim.1),
+        (rect_dim.0 - dim.0) * rect_dim.1);
+
+    if vert_vol > horiz_vol {
+        //     |AA
+        // ----+--
+        // BBBBBBB
+        // BBBBBBB
+        (Rect(V2(rect_pos.0 + dim.0, rect_pos.1), V2(rect_dim.0 - dim.0, dim.1)),
+         Rect(V2(rect_pos.0, rect_pos.1 + dim.1), V2(rect_dim.0, rect_dim.1 - dim.1)))
+    } else {
+        //     |BB
+        // ----+BB
+        // AAAA|BB
+        // AAAA|BB
+        (Rect(V2(rect_pos.0, rect_pos.1 + dim.1), V2(dim.0, rect_dim.1 - dim.1)),
+         Rect(V2(rect_pos.0 + dim.0, rect_pos.1), V2(rect_dim.0 - dim.0, rect_dim.1)))
     }
+}
 
-    fn fits<T: Ord>(dim: V2<T>, container_dim: V2<T>) -> bool {
-        dim.0 <= container_dim.0 && dim.1 <= container_dim.1
+fn fits<T: Ord>(dim: V2<T>, container_dim: V2<T>) -> bool {
+    dim.0 <= container_dim.0 && dim.1 <= container_dim.1
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_insert() {
+        use image::{ImageBuffer, Rgba};
+        use geom::V2;
+        use super::{AtlasBuilder, Atlas};
+
+        let image: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::from_fn(
+            4, 4, |_, _| Rgba([0xffu8, 0xffu8, 0xffu8, 0xffu8]));
+        let mut builder = AtlasBuilder::new();
+        builder.push(V2(0, 0), &image);
+        builder.reserve(100);
+        let mut atlas = Atlas::new(&builder);
+
+        assert_eq!(Some(1), atlas.insert(V2(0, 0), &image));
+        assert!(atlas.items[0].tex != atlas.items[1].tex);
+        assert_eq!(atlas.items[0].pos, atlas.items[1].pos);
+
+        // Must run out of space eventually.
+        let mut n = 0;
+        while atlas.insert(V2(0, 0), &image).is_some() {
+            n += 1;
+            assert!(n < 1000);
+        }
     }
 }
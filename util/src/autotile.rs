@@ -0,0 +1,214 @@
+/*!
+Choosing tile variants based on the neighboring tiles.
+
+A tile connects to each of its neighbors or not, and the connections are
+collected into a bit mask that picks the tile image variant. The 4-bit edge
+mask only looks at the orthogonal neighbors and needs 16 variants. The 8-bit
+blob mask also looks at the diagonal neighbors and needs 47 variants, since a
+diagonal neighbor only matters when both the orthogonal neighbors next to it
+are connected.
+ */
+
+use std::hash::Hash;
+use std::collections::HashMap;
+use geom::V2;
+use tilemap::Layer;
+
+/// A grid node that can find its neighbors in the eight compass directions.
+pub trait GridNode: Sized {
+    /// Return the neighbor in a direction. Direction 0 is north and the
+    /// directions go clockwise in 45 degree steps, so the even directions
+    /// are orthogonal and the odd ones diagonal.
+    fn neighbor(&self, dir: usize) -> Self;
+}
+
+/// Unit vectors for the eight compass directions, starting from north and
+/// going clockwise.
+pub static DIRECTIONS: [V2<i32>; 8] = [
+    V2(0, -1), V2(1, -1), V2(1, 0), V2(1, 1),
+    V2(0, 1), V2(-1, 1), V2(-1, 0), V2(-1, -1)];
+
+impl GridNode for V2<i32> {
+    fn neighbor(&self, dir: usize) -> V2<i32> { *self + DIRECTIONS[dir % 8] }
+}
+
+/// Return the 4-bit edge mask of a node. Bits 0 to 3 are set when the
+/// north, east, south and west neighbors connect to the node.
+pub fn edge_mask<N: GridNode, F: Fn(&N) -> bool>(node: &N, connects: F) -> u8 {
+    let mut ret = 0;
+    for i in 0..4 {
+        if connects(&node.neighbor(i * 2)) { ret |= 1 << i; }
+    }
+    ret
+}
+
+/// Return the 8-bit blob mask of a node. Bit i is set when the neighbor in
+/// direction i connects to the node. Diagonal bits are only set if both the
+/// orthogonal neighbors next to the diagonal are also connected.
+pub fn blob_mask<N: GridNode, F: Fn(&N) -> bool>(node: &N, connects: F) -> u8 {
+    let mut ret = 0;
+    for i in 0..8 {
+        if connects(&node.neighbor(i)) { ret |= 1 << i; }
+    }
+    normalize_blob(ret)
+}
+
+/// Clear the diagonal bits of a blob mask that have an unconnected
+/// orthogonal neighbor next to them.
+fn normalize_blob(mask: u8) -> u8 {
+    let mut ret = mask;
+    for i in 0..4 {
+        let diagonal = i * 2 + 1;
+        let (left, right) = (i * 2, (i * 2 + 2) % 8);
+        if mask & (1 << left) == 0 || mask & (1 << right) == 0 {
+            ret &= !(1 << diagonal);
+        }
+    }
+    ret
+}
+
+/// The 47 distinct blob masks in ascending order. The variants of a blob
+/// autotile are expected in this order.
+pub fn blob_masks() -> Vec<u8> {
+    (0..256).map(|m| m as u8).filter(|&m| normalize_blob(m) == m).collect()
+}
+
+/// Return the variant index of a blob mask, its position in blob_masks.
+pub fn blob_index(mask: u8) -> usize { BLOB_INDEX[mask as usize] as usize }
+
+/// Variant indices of all the 8-bit masks, the masks are normalized first.
+static BLOB_INDEX: [u8; 256] = [
+     0,  1,  0,  1,  2,  3,  2,  4,  0,  1,  0,  1,  2,  3,  2,  4,
+     5,  6,  5,  6,  7,  8,  7,  9,  5,  6,  5,  6, 10, 11, 10, 12,
+     0,  1,  0,  1,  2,  3,  2,  4,  0,  1,  0,  1,  2,  3,  2,  4,
+     5,  6,  5,  6,  7,  8,  7,  9,  5,  6,  5,  6, 10, 11, 10, 12,
+    13, 14, 13, 14, 15, 16, 15, 17, 13, 14, 13, 14, 15, 16, 15, 17,
+    18, 19, 18, 19, 20, 21, 20, 22, 18, 19, 18, 19, 23, 24, 23, 25,
+    13, 14, 13, 14, 15, 16, 15, 17, 13, 14, 13, 14, 15, 16, 15, 17,
+    26, 27, 26, 27, 28, 29, 28, 30, 26, 27, 26, 27, 31, 32, 31, 33,
+     0,  1,  0,  1,  2,  3,  2,  4,  0,  1,  0,  1,  2,  3,  2,  4,
+     5,  6,  5,  6,  7,  8,  7,  9,  5,  6,  5,  6, 10, 11, 10, 12,
+     0,  1,  0,  1,  2,  3,  2,  4,  0,  1,  0,  1,  2,  3,  2,  4,
+     5,  6,  5,  6,  7,  8,  7,  9,  5,  6,  5,  6, 10, 11, 10, 12,
+    13, 34, 13, 34, 15, 35, 15, 36, 13, 34, 13, 34, 15, 35, 15, 36,
+    18, 37, 18, 37, 20, 38, 20, 39, 18, 37, 18, 37, 23, 40, 23, 41,
+    13, 34, 13, 34, 15, 35, 15, 36, 13, 34, 13, 34, 15, 35, 15, 36,
+    26, 42, 26, 42, 28, 43, 28, 44, 26, 42, 26, 42, 31, 45, 31, 46,
+];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Rule {
+    /// 4-bit mask of the orthogonal neighbors, 16 variants.
+    Edge,
+    /// 8-bit mask of all the neighbors, 47 variants.
+    Blob,
+}
+
+/// A set of tile variants, usually atlas images, for a terrain kind.
+#[derive(Clone, Debug)]
+pub struct Autotile<T> {
+    rule: Rule,
+    variants: Vec<T>,
+}
+
+impl<T> Autotile<T> {
+    /// Create an edge mask autotile. The variants are indexed by the edge
+    /// mask.
+    pub fn edge(variants: Vec<T>) -> Autotile<T> {
+        assert!(variants.len() == 16, "Edge autotile needs 16 variants");
+        Autotile { rule: Rule::Edge, variants: variants }
+    }
+
+    /// Create a blob mask autotile. The variants are in the order of
+    /// blob_masks.
+    pub fn blob(variants: Vec<T>) -> Autotile<T> {
+        assert!(variants.len() == 47, "Blob autotile needs 47 variants");
+        Autotile { rule: Rule::Blob, variants: variants }
+    }
+
+    pub fn rule(&self) -> Rule { self.rule }
+
+    /// Return the variant for a node given the predicate that tells which
+    /// of its neighbors connect to it.
+    pub fn variant<N: GridNode, F: Fn(&N) -> bool>(&self, node: &N, connects: F) -> &T {
+        match self.rule {
+            Rule::Edge => &self.variants[edge_mask(node, connects) as usize],
+            Rule::Blob => &self.variants[blob_index(blob_mask(node, connects))],
+        }
+    }
+}
+
+/// Choose the tile variants for a layer of terrain kinds. Cells connect to
+/// neighbors of the same kind and to positions outside the layer, so that
+/// terrain continues past the map edge. Cells whose kind has no autotile
+/// get None.
+pub fn autotile_layer<K, T>(layer: &Layer<K>, tiles: &HashMap<K, Autotile<T>>) -> Layer<Option<T>>
+    where K: Eq+Hash, T: Clone {
+    let mut ret = Layer::new(layer.size(), None);
+    for (pos, kind) in layer.iter() {
+        if let Some(tile) = tiles.get(kind) {
+            let variant = tile.variant(&pos, |p| layer.get(*p).map_or(true, |k| k == kind));
+            ret.set(pos, Some(variant.clone()));
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_masks() {
+        use super::{edge_mask, blob_mask, blob_masks, blob_index, normalize_blob};
+        use geom::V2;
+        use tilemap::TileMap;
+
+        let map = TileMap::from_text("\
+.#.
+###
+##.", ' ', |c| c);
+        let wall = |p: &V2<i32>| map.layer(0).get(*p) == Some(&'#');
+
+        assert_eq!(0b1111, edge_mask(&V2(1, 1), &wall));
+        assert_eq!(0b0110, edge_mask(&V2(0, 1), &wall));
+        assert_eq!(0b0100, edge_mask(&V2(1, 0), &wall));
+        // Only the south west diagonal has both of its orthogonal
+        // neighbors connected.
+        assert_eq!(0b0111_0101, blob_mask(&V2(1, 1), &wall));
+        // The southern diagonals don't count without the orthogonals.
+        assert_eq!(0b0001_0000, blob_mask(&V2(1, 0), &wall));
+
+        let masks = blob_masks();
+        assert_eq!(47, masks.len());
+        for (i, &m) in masks.iter().enumerate() {
+            assert_eq!(i, blob_index(m));
+        }
+        assert_eq!(46, blob_index(0xff));
+        // The lookup table matches the masks.
+        for m in 0..256 {
+            let m = m as u8;
+            assert_eq!(masks.iter().position(|&x| x == normalize_blob(m)), Some(blob_index(m)));
+        }
+        assert_eq!(blob_index(0b0100), blob_index(0b0110));
+    }
+
+    #[test]
+    fn test_autotile_layer() {
+        use std::collections::HashMap;
+        use super::{Autotile, autotile_layer};
+        use geom::V2;
+        use tilemap::TileMap;
+
+        let map = TileMap::from_text("\
+~~.
+~..", '.', |c| c);
+        let mut tiles = HashMap::new();
+        tiles.insert('~', Autotile::edge((0..16).collect()));
+        let water = autotile_layer(map.layer(0), &tiles);
+
+        // Connects to the map edge to the north and west.
+        assert_eq!(Some(0b1111), water[V2(0, 0)]);
+        assert_eq!(Some(0b1001), water[V2(1, 0)]);
+        assert_eq!(Some(0b1101), water[V2(0, 1)]);
+        assert_eq!(None, water[V2(2, 1)]);
+    }
+}
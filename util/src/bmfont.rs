@@ -0,0 +1,168 @@
+/*!
+Parser for the text format font descriptors of the BMFont bitmap font tool.
+
+See http://www.angelcode.com/products/bmfont/doc/file_format.html for the
+format description.
+ */
+
+use std::str::FromStr;
+use geom::{V2, Rect};
+
+/// Font metrics and glyph locations parsed from a BMFont descriptor.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BmFont {
+    /// Distance in pixels between successive lines of text.
+    pub line_height: i32,
+    /// Distance in pixels from the top of a line to the baseline.
+    pub base: i32,
+    /// Texture page image file names, indexed by page id.
+    pub pages: Vec<String>,
+    pub chars: Vec<BmChar>,
+    /// (first, second, amount) kerning adjustments for character pairs.
+    pub kernings: Vec<(char, char, i32)>,
+}
+
+/// A single glyph in a BMFont texture page.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BmChar {
+    pub id: char,
+    /// Glyph rectangle on the texture page.
+    pub rect: Rect<i32>,
+    /// Offset to add to the pen position when drawing the glyph, relative to
+    /// the top of the line.
+    pub offset: V2<i32>,
+    /// How much to advance the pen position after drawing the glyph.
+    pub advance: i32,
+    /// Texture page index of the glyph.
+    pub page: usize,
+}
+
+impl BmFont {
+    /// Parse a text format BMFont descriptor.
+    pub fn parse(text: &str) -> Result<BmFont, String> {
+        let mut ret = BmFont {
+            line_height: 0,
+            base: 0,
+            pages: Vec::new(),
+            chars: Vec::new(),
+            kernings: Vec::new(),
+        };
+
+        for line in text.lines() {
+            let (tag, attrs) = tokenize(line);
+            match &tag[..] {
+                "common" => {
+                    ret.line_height = try!(attr(&attrs, "lineHeight"));
+                    ret.base = try!(attr(&attrs, "base"));
+                }
+                "page" => {
+                    let id: usize = try!(attr(&attrs, "id"));
+                    let file: String = try!(attr(&attrs, "file"));
+                    while ret.pages.len() <= id { ret.pages.push(String::new()); }
+                    ret.pages[id] = file;
+                }
+                "char" => {
+                    ret.chars.push(BmChar {
+                        id: try!(char_attr(&attrs, "id")),
+                        rect: Rect(
+                            V2(try!(attr(&attrs, "x")), try!(attr(&attrs, "y"))),
+                            V2(try!(attr(&attrs, "width")), try!(attr(&attrs, "height")))),
+                        offset: V2(try!(attr(&attrs, "xoffset")), try!(attr(&attrs, "yoffset"))),
+                        advance: try!(attr(&attrs, "xadvance")),
+                        page: try!(attr(&attrs, "page")),
+                    });
+                }
+                "kerning" => {
+                    ret.kernings.push((
+                        try!(char_attr(&attrs, "first")),
+                        try!(char_attr(&attrs, "second")),
+                        try!(attr(&attrs, "amount"))));
+                }
+                // Ignore info, chars, kernings and anything else we don't
+                // need.
+                _ => {}
+            }
+        }
+
+        if ret.line_height <= 0 {
+            return Err("Missing or bad common block".to_string());
+        }
+
+        Ok(ret)
+    }
+}
+
+fn attr<T: FromStr>(attrs: &Vec<(String, String)>, key: &str) -> Result<T, String> {
+    match attrs.iter().find(|&&(ref k, _)| &k[..] == key) {
+        Some(&(_, ref v)) => v.parse().map_err(|_| format!("Bad value for {}: {}", key, v)),
+        None => Err(format!("Missing attribute {}", key))
+    }
+}
+
+fn char_attr(attrs: &Vec<(String, String)>, key: &str) -> Result<char, String> {
+    let code: u32 = try!(attr(attrs, key));
+    ::std::char::from_u32(code).ok_or(format!("Bad character code {}", code))
+}
+
+/// Split a descriptor line into the leading tag and the list of key=value
+/// attributes. Values may be quoted to contain whitespace.
+fn tokenize(line: &str) -> (String, Vec<(String, String)>) {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+
+    for c in line.chars() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if c.is_whitespace() && !quoted {
+            if token.len() > 0 { tokens.push(token); }
+            token = String::new();
+        } else {
+            token.push(c);
+        }
+    }
+    if token.len() > 0 { tokens.push(token); }
+
+    if tokens.len() == 0 { return (String::new(), Vec::new()); }
+
+    let tag = tokens.remove(0);
+    let attrs = tokens.into_iter().filter_map(|t| {
+        t.find('=').map(|i| (t[..i].to_string(), t[i + 1..].to_string()))
+    }).collect();
+    (tag, attrs)
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_parse() {
+        use super::{BmFont, BmChar};
+        use geom::{V2, Rect};
+
+        let font = BmFont::parse(r#"info face="Some Font" size=16 bold=0
+common lineHeight=18 base=14 scaleW=256 scaleH=256 pages=1 packed=0
+page id=0 file="some font_0.png"
+chars count=2
+char id=32   x=0     y=0     width=0     height=0     xoffset=0     yoffset=14    xadvance=4     page=0  chnl=15
+char id=228  x=10    y=20    width=7     height=10    xoffset=1     yoffset=4     xadvance=8     page=0  chnl=15
+kernings count=1
+kerning first=32  second=228 amount=-1
+"#).unwrap();
+
+        assert_eq!(18, font.line_height);
+        assert_eq!(14, font.base);
+        assert_eq!(vec!["some font_0.png".to_string()], font.pages);
+        assert_eq!(2, font.chars.len());
+        assert_eq!(BmChar {
+            id: 'ä',
+            rect: Rect(V2(10, 20), V2(7, 10)),
+            offset: V2(1, 4),
+            advance: 8,
+            page: 0,
+        }, font.chars[1]);
+        assert_eq!(vec![(' ', 'ä', -1)], font.kernings);
+
+        assert!(BmFont::parse("").is_err());
+        assert!(BmFont::parse("common lineHeight=x base=2").is_err());
+    }
+}
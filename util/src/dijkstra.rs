@@ -1,11 +1,51 @@
 use std::hash::Hash;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::BinaryHeap;
+use std::collections::hash_map;
+use std::ops::{Add, Mul};
 
 /// A grid node for the Dijkstra map.
 pub trait DijkstraNode: Eq+Clone+Hash {
     /// List the neighbor nodes of this graph node.
     fn neighbors(&self) -> Vec<Self>;
+
+    /// Cost of moving from this node to a neighbor node. Nodes with
+    /// varying terrain costs override this, by default every step costs 1.
+    fn cost(&self, _neighbor: &Self) -> u32 { 1 }
+}
+
+/// Queries shared by the Dijkstra map types.
+pub trait DijkstraMap<N: Clone> {
+    /// Return the map value of a node, the distance to the nearest goal
+    /// for an unmodified map, or None if the node is not covered by the map.
+    fn get(&self, node: &N) -> Option<f32>;
+
+    /// Return the neighbors of a node (if any), sorted from downhill to
+    /// uphill. A neighbor is as far down as its map value plus the cost of
+    /// stepping there, so the first one is the next step on the cheapest
+    /// route.
+    fn sorted_neighbors(&self, node: &N) -> Vec<N>;
+
+    /// Walk downhill from a node until reaching a goal or other local
+    /// minimum. Returns the nodes stepped on after the starting node, so an
+    /// empty path means the node is already at the bottom or is not on the
+    /// map.
+    fn path_from(&self, node: &N) -> Vec<N> {
+        let mut ret = Vec::new();
+        let mut node = node.clone();
+        while let Some(w) = self.get(&node) {
+            let next = match self.sorted_neighbors(&node).into_iter().next() {
+                Some(n) => n,
+                None => break
+            };
+            if self.get(&next).unwrap() >= w { break; }
+            ret.push(next.clone());
+            node = next;
+        }
+        ret
+    }
 }
 
 /// A pathfinding map structure. A Dijkstra map lets you run pathfinding from
@@ -13,38 +53,43 @@ pub trait DijkstraNode: Eq+Clone+Hash {
 /// Currently the structure only supports underlying graphs with a fixed grid graph
 /// where the neighbors of each node must be the adjacent grid cells of that
 /// node.
+///
+/// Maps can be scaled and added together to combine several goals, eg. to
+/// approach treasure while keeping away from monsters.
+#[derive(Clone)]
 pub struct Dijkstra<N> {
-    weights: HashMap<N, u32>,
+    weights: HashMap<N, f32>,
 }
 
 impl<N: DijkstraNode> Dijkstra<N> {
-    /// Create a new Dijkstra map up to limit distance from goals, omitting
-    /// nodes for which the is_valid predicate returns false.
+    /// Create a new Dijkstra map of the nodes whose shortest distance from
+    /// the goals is less than the limit cost, omitting nodes for which the
+    /// is_valid predicate returns false.
     pub fn new<F: Fn(&N) -> bool>(goals: Vec<N>, is_valid: F, limit: u32) -> Dijkstra<N> {
         assert!(goals.len() > 0);
 
         let mut weights = HashMap::new();
-        let mut edge = HashSet::new();
+        let mut queue = BinaryHeap::new();
 
         for n in goals.into_iter() {
-            edge.insert(n);
+            queue.push(Frontier { cost: 0, node: n });
         }
 
-        for dist in 0..(limit) {
-            for n in edge.iter() {
-                weights.insert(n.clone(), dist);
-            }
+        while let Some(Frontier { cost, node }) = queue.pop() {
+            if cost >= limit { break; }
+            // Nodes get pushed again when a shorter route to them is found,
+            // skip the stale entries.
+            if weights.contains_key(&node) { continue; }
+            weights.insert(node.clone(), cost as f32);
 
-            let mut new_edge = HashSet::new();
-            for n in edge.iter() {
-                for m in n.neighbors().into_iter() {
-                    if is_valid(&m) && !weights.contains_key(&m) {
-                        new_edge.insert(m);
-                    }
+            for m in node.neighbors().into_iter() {
+                if is_valid(&m) && !weights.contains_key(&m) {
+                    // The map is walked towards the goals, so the cost is
+                    // that of stepping from m back to node.
+                    let step = m.cost(&node);
+                    queue.push(Frontier { cost: cost + step, node: m });
                 }
             }
-
-            edge = new_edge;
         }
 
         Dijkstra {
@@ -52,16 +97,193 @@ impl<N: DijkstraNode> Dijkstra<N> {
         }
     }
 
-    /// Return the neighbors of a cell (if any), sorted from downhill to
-    /// uphill.
-    pub fn sorted_neighbors(& self, node: &N) -> Vec<N> {
+    /// Iterate the nodes of the map and their values.
+    pub fn iter<'a>(&'a self) -> hash_map::Iter<'a, N, f32> {
+        self.weights.iter()
+    }
+
+    /// Let the values flow downhill along the graph edges until every node
+    /// is at most its step cost above each of its neighbors, like in a
+    /// freshly computed map. Used after modifying the map values with
+    /// arithmetic.
+    pub fn rescan(&mut self) {
+        let mut queue = BinaryHeap::new();
+        for (n, &w) in self.weights.iter() {
+            queue.push(Frontier { cost: w, node: n.clone() });
+        }
+
+        let mut done = HashSet::new();
+        while let Some(Frontier { cost, node }) = queue.pop() {
+            if done.contains(&node) { continue; }
+            for m in node.neighbors().into_iter() {
+                if done.contains(&m) { continue; }
+                let new_cost = cost + m.cost(&node) as f32;
+                let improved = self.weights.get(&m).map_or(false, |&w| new_cost < w);
+                if improved {
+                    self.weights.insert(m.clone(), new_cost);
+                    queue.push(Frontier { cost: new_cost, node: m });
+                }
+            }
+            done.insert(node);
+        }
+    }
+
+    /// Turn a map for approaching the goals into one for fleeing from them.
+    /// Simply inverting the map makes creatures run into dead ends, scaling
+    /// it by a factor a bit more negative than -1 and rescanning makes
+    /// routes that lead past the threat and into open space preferable.
+    pub fn flee_map(&self) -> Dijkstra<N> {
+        let mut ret = self.clone() * -1.2;
+        ret.rescan();
+        ret
+    }
+}
+
+impl<N: DijkstraNode> DijkstraMap<N> for Dijkstra<N> {
+    fn get(&self, node: &N) -> Option<f32> {
+        self.weights.get(node).map(|&w| w)
+    }
+
+    fn sorted_neighbors(&self, node: &N) -> Vec<N> {
         let mut ret = Vec::new();
         for n in node.neighbors().iter() {
             if let Some(w) = self.weights.get(n) {
-                ret.push((w, n.clone()));
+                ret.push((*w + node.cost(n) as f32, n.clone()));
             }
         }
-        ret.sort_by(|&(w1, _), &(w2, _)| w1.cmp(w2));
+        ret.sort_by(|&(w1, _), &(w2, _)| w1.partial_cmp(&w2).unwrap_or(Ordering::Equal));
         ret.into_iter().map(|(_, n)| n).collect()
     }
 }
+
+/// Scale the values of the map by a coefficient.
+impl<N: DijkstraNode> Mul<f32> for Dijkstra<N> {
+    type Output = Dijkstra<N>;
+    fn mul(mut self, rhs: f32) -> Dijkstra<N> {
+        for w in self.weights.values_mut() { *w = *w * rhs; }
+        self
+    }
+}
+
+/// Sum two maps. The result only covers the nodes covered by both maps.
+impl<N: DijkstraNode> Add<Dijkstra<N>> for Dijkstra<N> {
+    type Output = Dijkstra<N>;
+    fn add(self, rhs: Dijkstra<N>) -> Dijkstra<N> {
+        let mut weights = HashMap::new();
+        for (n, w) in self.weights.into_iter() {
+            if let Some(v) = rhs.weights.get(&n) {
+                weights.insert(n, w + *v);
+            }
+        }
+        Dijkstra { weights: weights }
+    }
+}
+
+/// Priority queue entry for graph searches. The ordering is reversed so
+/// that the standard max-heap pops the lowest cost entry first.
+pub struct Frontier<C, N> {
+    pub cost: C,
+    pub node: N,
+}
+
+impl<C: PartialOrd, N> PartialEq for Frontier<C, N> {
+    fn eq(&self, other: &Frontier<C, N>) -> bool { self.cmp(other) == Ordering::Equal }
+}
+
+impl<C: PartialOrd, N> Eq for Frontier<C, N> {}
+
+impl<C: PartialOrd, N> PartialOrd for Frontier<C, N> {
+    fn partial_cmp(&self, other: &Frontier<C, N>) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl<C: PartialOrd, N> Ord for Frontier<C, N> {
+    fn cmp(&self, other: &Frontier<C, N>) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use geom::V2;
+    use super::{DijkstraNode, DijkstraMap};
+
+    /// Grid position on a test map where stepping into '~' swamp costs 3.
+    #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+    struct Cell(V2<i32>);
+
+    static MAP: [&'static str; 3] = [
+        "..~.",
+        ".~~.",
+        "....",
+    ];
+
+    impl DijkstraNode for Cell {
+        fn neighbors(&self) -> Vec<Cell> {
+            let Cell(V2(x, y)) = *self;
+            vec![V2(x, y - 1), V2(x + 1, y), V2(x, y + 1), V2(x - 1, y)].into_iter()
+                .filter(|p| p.0 >= 0 && p.1 >= 0 && p.0 < 4 && p.1 < 3)
+                .map(|p| Cell(p)).collect()
+        }
+
+        fn cost(&self, neighbor: &Cell) -> u32 {
+            let Cell(V2(x, y)) = *neighbor;
+            if MAP[y as usize].as_bytes()[x as usize] == b'~' { 3 } else { 1 }
+        }
+    }
+
+    #[test]
+    fn test_weighted() {
+        use super::Dijkstra;
+
+        let map = Dijkstra::new(vec![Cell(V2(0, 0))], |_| true, 100);
+        // Wading through the swamp at (2, 0) costs 3 + 1 + 1, which still
+        // beats the 7 of going around it.
+        assert_eq!(Some(5.0), map.get(&Cell(V2(3, 0))));
+        // Stepping out of the swamp is cheap.
+        assert_eq!(Some(2.0), map.get(&Cell(V2(1, 1))));
+        assert_eq!(Some(5.0), map.get(&Cell(V2(2, 1))));
+        assert_eq!(Some(6.0), map.get(&Cell(V2(3, 1))));
+        assert_eq!(vec![Cell(V2(3, 0)), Cell(V2(3, 2)), Cell(V2(2, 1))],
+                   map.sorted_neighbors(&Cell(V2(3, 1))));
+
+        // The limit is a cost budget.
+        let map = Dijkstra::new(vec![Cell(V2(0, 0))], |_| true, 3);
+        assert!(map.get(&Cell(V2(1, 0))).is_some());
+        assert_eq!(Some(2.0), map.get(&Cell(V2(1, 1))));
+        assert_eq!(None, map.get(&Cell(V2(1, 2))));
+        assert_eq!(None, map.get(&Cell(V2(2, 1))));
+    }
+
+    #[test]
+    fn test_composition() {
+        use super::Dijkstra;
+
+        let map = Dijkstra::new(vec![Cell(V2(0, 0))], |_| true, 100);
+        assert_eq!(vec![Cell(V2(3, 0)), Cell(V2(2, 0)), Cell(V2(1, 0)), Cell(V2(0, 0))],
+                   map.path_from(&Cell(V2(3, 1))));
+        assert_eq!(Vec::<Cell>::new(), map.path_from(&Cell(V2(0, 0))));
+
+        let near = Dijkstra::new(vec![Cell(V2(0, 0))], |_| true, 3);
+        let sum = map.clone() * 2.0 + near;
+        assert_eq!(Some(3.0), sum.get(&Cell(V2(0, 1))));
+        // Only covered by one of the maps.
+        assert_eq!(None, sum.get(&Cell(V2(3, 0))));
+
+        let flee = map.flee_map();
+        // Flees to the spot furthest from the goal.
+        assert_eq!(Some(&Cell(V2(3, 1))), flee.path_from(&Cell(V2(0, 0))).last());
+        // Rescanning pulls the cells next to the far spot below their
+        // inverted distance.
+        assert!(flee.get(&Cell(V2(3, 2))).unwrap() < 5.0 * -1.2);
+        // The swamp costs the same when fleeing, no cell is more than the
+        // cost of a step above its neighbors.
+        for (n, &w) in flee.iter() {
+            for m in n.neighbors().iter() {
+                assert!(w <= flee.get(m).unwrap() + n.cost(m) as f32 + 1e-4);
+            }
+        }
+        // Fleeing through the swamp cell at (2, 0) costs 3.
+        assert!((flee.get(&Cell(V2(2, 0))).unwrap() - -5.2).abs() < 1e-4);
+        assert!((flee.get(&Cell(V2(1, 0))).unwrap() - -2.2).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,319 @@
+/*!
+Field of view and line of sight on grids.
+
+The field of view uses symmetric shadowcasting: a floor cell is visible
+exactly when the center of the viewer's cell is visible from it, so
+creatures that see each other always agree about it. The grid is scanned as
+sectors of rows moving away from the origin, eight octants on a square grid
+and six sextants on a hex grid.
+ */
+
+use std::collections::HashSet;
+use std::mem;
+use geom::V2;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Topology {
+    /// Square grid where the eight surrounding cells are neighbors.
+    Square,
+    /// Hex grid in axial coordinates. The neighbor directions are (1, 0),
+    /// (1, -1), (0, -1), (-1, 0), (-1, 1) and (0, 1).
+    Hex,
+}
+
+static SQUARE_SECTORS: [(V2<i32>, V2<i32>); 8] = [
+    (V2(0, -1), V2(1, 0)), (V2(0, -1), V2(-1, 0)),
+    (V2(1, 0), V2(0, -1)), (V2(1, 0), V2(0, 1)),
+    (V2(0, 1), V2(1, 0)), (V2(0, 1), V2(-1, 0)),
+    (V2(-1, 0), V2(0, -1)), (V2(-1, 0), V2(0, 1))];
+
+// Each row goes from the corner in one hex direction to the corner in the
+// next one.
+static HEX_SECTORS: [(V2<i32>, V2<i32>); 6] = [
+    (V2(1, 0), V2(0, -1)), (V2(1, -1), V2(-1, 0)),
+    (V2(0, -1), V2(-1, 1)), (V2(-1, 0), V2(0, 1)),
+    (V2(-1, 1), V2(1, 0)), (V2(0, 1), V2(1, -1))];
+
+/// Field of view parameters.
+#[derive(Copy, Clone, Debug)]
+pub struct Fov {
+    topology: Topology,
+    radius: Option<i32>,
+    light_walls: bool,
+}
+
+impl Fov {
+    /// Create an unlimited field of view that includes the walls bounding
+    /// the visible area.
+    pub fn new(topology: Topology) -> Fov {
+        Fov {
+            topology: topology,
+            radius: None,
+            light_walls: true,
+        }
+    }
+
+    /// Limit the field of view to cells within radius from the origin.
+    pub fn radius(mut self, radius: i32) -> Fov {
+        self.radius = Some(radius);
+        self
+    }
+
+    /// Set whether the opaque cells at the edge of the visible area are
+    /// visible. If not, only the cells that pass the same symmetric
+    /// visibility test as the floor cells are.
+    pub fn light_walls(mut self, light_walls: bool) -> Fov {
+        self.light_walls = light_walls;
+        self
+    }
+
+    /// Call visit with each cell visible from origin. Cells on the
+    /// boundaries of the scanned sectors may get visited more than once.
+    pub fn scan<F, G>(&self, origin: V2<i32>, is_opaque: F, mut visit: G)
+        where F: Fn(V2<i32>) -> bool, G: FnMut(V2<i32>) {
+        visit(origin);
+        let sectors: &[(V2<i32>, V2<i32>)] = match self.topology {
+            Topology::Square => &SQUARE_SECTORS,
+            Topology::Hex => &HEX_SECTORS,
+        };
+        for &(dir, side) in sectors.iter() {
+            let sector = Sector { origin: origin, dir: dir, side: side };
+            self.scan_row(&sector, 1, Slope(0, 1), Slope(1, 1), &is_opaque, &mut visit);
+        }
+    }
+
+    /// Return the set of cells visible from origin.
+    pub fn visible<F: Fn(V2<i32>) -> bool>(&self, origin: V2<i32>, is_opaque: F) -> HashSet<V2<i32>> {
+        let mut ret = HashSet::new();
+        self.scan(origin, is_opaque, |p| { ret.insert(p); });
+        ret
+    }
+
+    fn in_radius(&self, sector: &Sector, depth: i32, col: i32) -> bool {
+        match (self.radius, self.topology) {
+            (None, _) => true,
+            // Every cell in a hex sector row is at the same distance.
+            (Some(r), Topology::Hex) => depth <= r,
+            (Some(r), Topology::Square) => {
+                let V2(x, y) = sector.cell(depth, col) - sector.origin;
+                4 * (x * x + y * y) <= (2 * r + 1) * (2 * r + 1)
+            }
+        }
+    }
+
+    /// Scan the row at depth between the start and end slopes, and
+    /// recurse into the lit parts of the next row.
+    fn scan_row<F, G>(&self, sector: &Sector, depth: i32, start: Slope, end: Slope,
+                      is_opaque: &F, visit: &mut G)
+        where F: Fn(V2<i32>) -> bool, G: FnMut(V2<i32>) {
+        if let Some(r) = self.radius {
+            if depth > r { return; }
+        }
+        let mut start = start;
+        // Columns whose cells overlap the slope range.
+        let first = floor_div(2 * depth * start.0 + start.1, 2 * start.1);
+        let last = ceil_div(2 * depth * end.0 - end.1, 2 * end.1);
+
+        let mut prev_wall = None;
+        for col in first..(last + 1) {
+            let pos = sector.cell(depth, col);
+            let wall = is_opaque(pos);
+            if self.in_radius(sector, depth, col) &&
+                ((wall && self.light_walls) || is_symmetric(depth, col, start, end)) {
+                visit(pos);
+            }
+            match mem::replace(&mut prev_wall, Some(wall)) {
+                Some(true) if !wall => { start = edge_slope(depth, col); }
+                Some(false) if wall => {
+                    self.scan_row(sector, depth + 1, start, edge_slope(depth, col), is_opaque, visit);
+                }
+                _ => {}
+            }
+        }
+        if prev_wall == Some(false) {
+            self.scan_row(sector, depth + 1, start, end, is_opaque, visit);
+        }
+    }
+}
+
+struct Sector {
+    origin: V2<i32>,
+    /// Direction away from the origin.
+    dir: V2<i32>,
+    /// Direction along the rows.
+    side: V2<i32>,
+}
+
+impl Sector {
+    fn cell(&self, depth: i32, col: i32) -> V2<i32> {
+        self.origin + V2(self.dir.0 * depth + self.side.0 * col, self.dir.1 * depth + self.side.1 * col)
+    }
+}
+
+/// A rational slope numerator / denominator, the denominator is positive.
+#[derive(Copy, Clone, Debug)]
+struct Slope(i32, i32);
+
+/// Slope to the edge between a column and the previous one.
+fn edge_slope(depth: i32, col: i32) -> Slope { Slope(2 * col - 1, 2 * depth) }
+
+/// Whether the center of a cell is inside the slope range.
+fn is_symmetric(depth: i32, col: i32, start: Slope, end: Slope) -> bool {
+    col * start.1 >= depth * start.0 && col * end.1 <= depth * end.0
+}
+
+fn floor_div(a: i32, b: i32) -> i32 {
+    if a >= 0 { a / b } else { -((-a + b - 1) / b) }
+}
+
+fn ceil_div(a: i32, b: i32) -> i32 { -floor_div(-a, b) }
+
+/// Return the cells on a Bresenham line from a to b, including both ends.
+///
+/// The line between two points always passes through the same cells
+/// regardless of which end it's drawn from.
+pub fn line(a: V2<i32>, b: V2<i32>) -> Vec<V2<i32>> {
+    // Always draw from the smaller point to make the line symmetric.
+    if (b.0, b.1) < (a.0, a.1) {
+        let mut ret = line(b, a);
+        ret.reverse();
+        return ret;
+    }
+
+    let (dx, dy) = ((b.0 - a.0).abs(), -(b.1 - a.1).abs());
+    let step = V2(if a.0 < b.0 { 1 } else { -1 }, if a.1 < b.1 { 1 } else { -1 });
+    let mut err = dx + dy;
+    let mut pos = a;
+    let mut ret = vec![pos];
+    while pos != b {
+        let e2 = 2 * err;
+        if e2 >= dy { err += dy; pos.0 += step.0; }
+        if e2 <= dx { err += dx; pos.1 += step.1; }
+        ret.push(pos);
+    }
+    ret
+}
+
+/// Return whether there is a line of sight between a and b. The end
+/// points themselves may be opaque.
+pub fn line_of_sight<F: Fn(V2<i32>) -> bool>(a: V2<i32>, b: V2<i32>, is_opaque: F) -> bool {
+    let cells = line(a, b);
+    cells.len() < 3 || cells[1..cells.len() - 1].iter().all(|&p| !is_opaque(p))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use geom::V2;
+    use text::Map2DUtil;
+
+    /// Parse an ASCII map into the walls and the origin '@'.
+    fn parse(map: &str) -> (HashSet<V2<i32>>, V2<i32>) {
+        let mut walls = HashSet::new();
+        let mut origin = V2(0, 0);
+        for (c, x, y) in map.chars().map2d() {
+            if c == '#' { walls.insert(V2(x, y)); }
+            if c == '@' { origin = V2(x, y); }
+        }
+        (walls, origin)
+    }
+
+    /// Redraw the map with only the visible cells shown.
+    fn render(map: &str, visible: &HashSet<V2<i32>>) -> String {
+        let mut lines: Vec<String> = map.lines().map(|_| String::new()).collect();
+        for (c, x, y) in map.chars().map2d() {
+            lines[y as usize].push(if visible.contains(&V2(x, y)) { c } else { ' ' });
+        }
+        let lines: Vec<&str> = lines.iter().map(|s| s.trim_right()).collect();
+        lines.connect("\n")
+    }
+
+    #[test]
+    fn test_square_fov() {
+        use super::{Fov, Topology};
+
+        let map = "
+###########
+#.........#
+#..#......#
+#...@..#..#
+#.........#
+###########";
+        let (walls, origin) = parse(map);
+        let fov = Fov::new(Topology::Square);
+        assert_eq!(render(map, &fov.visible(origin, |p| walls.contains(&p))), "
+   ########
+#  .......#
+#..#......#
+#...@..#
+#.........#
+###########");
+        // The bottom right corner is only touched by the edge of the view.
+        assert_eq!(render(map, &fov.light_walls(false).visible(origin, |p| walls.contains(&p))), "
+   ########
+#  .......#
+#..#......#
+#...@..#
+#.........#
+#########");
+
+        // Symmetry: the origin sees a cell exactly when the cell sees the
+        // origin.
+        for &p in fov.visible(origin, |p| walls.contains(&p)).iter() {
+            if !walls.contains(&p) {
+                assert!(fov.visible(p, |p| walls.contains(&p)).contains(&origin));
+            }
+        }
+
+        let map = "
+.......
+.......
+...@...
+.......
+.......";
+        let (walls, origin) = parse(map);
+        assert_eq!(render(map, &fov.radius(2).visible(origin, |p| walls.contains(&p))), "
+  ...
+ .....
+ ..@..
+ .....
+  ...");
+    }
+
+    #[test]
+    fn test_hex_fov() {
+        use super::{Fov, Topology};
+
+        let fov = Fov::new(Topology::Hex).radius(2);
+        assert_eq!(19, fov.visible(V2(0, 0), |_| false).len());
+        let visible = fov.visible(V2(0, 0), |p| p == V2(1, 0));
+        assert!(visible.contains(&V2(1, 0)));
+        // Behind the wall.
+        assert!(!visible.contains(&V2(2, 0)));
+        assert!(visible.contains(&V2(2, -1)));
+    }
+
+    #[test]
+    fn test_line_of_sight() {
+        use super::{line, line_of_sight};
+
+        assert_eq!(vec![V2(0, 0), V2(1, 0), V2(2, 1), V2(3, 1)], line(V2(0, 0), V2(3, 1)));
+        assert_eq!(vec![V2(3, 1), V2(2, 1), V2(1, 0), V2(0, 0)], line(V2(3, 1), V2(0, 0)));
+        assert_eq!(vec![V2(2, 2)], line(V2(2, 2), V2(2, 2)));
+
+        let map = "\
+......
+..#...
+......";
+        let (walls, _) = parse(map);
+        let opaque = |p| walls.contains(&p);
+        assert!(!line_of_sight(V2(0, 1), V2(5, 1), &opaque));
+        assert!(line_of_sight(V2(0, 0), V2(5, 0), &opaque));
+        assert!(line_of_sight(V2(2, 1), V2(2, 2), &opaque));
+        for a in (0..6).flat_map(|x| (0..3).map(move |y| V2(x, y))) {
+            for b in (0..6).flat_map(|x| (0..3).map(move |y| V2(x, y))) {
+                assert_eq!(line_of_sight(a, b, &opaque), line_of_sight(b, a, &opaque));
+            }
+        }
+    }
+}
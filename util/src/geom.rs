@@ -4,7 +4,7 @@ use primitive::Primitive;
 use ::{Anchor};
 
 /// 2D geometric vector.
-#[derive(Copy, Debug, PartialEq, PartialOrd, Clone, Default, RustcDecodable, RustcEncodable)]
+#[derive(Copy, Debug, PartialEq, PartialOrd, Clone, Hash, Default, RustcDecodable, RustcEncodable)]
 pub struct V2<T>(pub T, pub T);
 
 impl<T: Eq> Eq for V2<T> { }
@@ -0,0 +1,390 @@
+use std::u32;
+use geom::{V2, Rect};
+use dijkstra::DijkstraMap;
+
+const UNREACHED: u32 = u32::MAX;
+
+/// A Dijkstra map over the cells of a rectangular grid area.
+///
+/// Works like `Dijkstra<V2<i32>>`, but keeps the distances in flat arrays
+/// and uses a bucket queue instead of a heap, which makes it much faster to
+/// recompute for large maps. When a single cell changes, `set_cost` only
+/// recomputes the part of the map affected by the change.
+///
+/// The incremental updates rely on the map values being exact integer
+/// distances, so unlike `Dijkstra` the map can't be scaled or summed. Both
+/// map types implement the `DijkstraMap` queries.
+pub struct GridDijkstra {
+    area: Rect<i32>,
+    dirs: Vec<V2<i32>>,
+    /// Cost of entering each cell, None for impassable cells.
+    costs: Vec<Option<u32>>,
+    dists: Vec<u32>,
+    goals: Vec<bool>,
+    limit: u32,
+    queue: BucketQueue,
+}
+
+impl GridDijkstra {
+    /// Create a new Dijkstra map over the area. The cell neighbors are the
+    /// cells at the dirs offsets, which must be symmetric, eg. the four or
+    /// eight grid directions. The cost function gives the cost of entering
+    /// a cell, None for cells that can't be entered. A cell's distance is
+    /// the sum of the costs of the cells entered on the way to the nearest
+    /// goal. Cells whose distance from the goals is not less than the limit
+    /// are left out of the map.
+    pub fn new<F: Fn(V2<i32>) -> Option<u32>>(
+        area: Rect<i32>, dirs: &[V2<i32>], goals: &[V2<i32>], cost: F, limit: u32) -> GridDijkstra {
+        assert!(goals.len() > 0);
+        let n = (area.1).0 as usize * (area.1).1 as usize;
+        let mut ret = GridDijkstra {
+            area: area,
+            dirs: dirs.to_vec(),
+            costs: area.iter().map(|p| cost(p)).collect(),
+            dists: (0..n).map(|_| UNREACHED).collect(),
+            goals: (0..n).map(|_| false).collect(),
+            limit: limit,
+            queue: BucketQueue::new(),
+        };
+
+        for &p in goals.iter() {
+            if let Some(i) = ret.index(p) {
+                ret.goals[i] = true;
+                ret.dists[i] = 0;
+                ret.queue.push(0, i);
+            }
+        }
+        ret.run();
+        ret
+    }
+
+    fn index(&self, pos: V2<i32>) -> Option<usize> {
+        if self.area.contains(&pos) {
+            let p = pos - self.area.0;
+            Some((p.0 + p.1 * (self.area.1).0) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn pos(&self, idx: usize) -> V2<i32> {
+        let w = (self.area.1).0 as usize;
+        self.area.0 + V2((idx % w) as i32, (idx / w) as i32)
+    }
+
+    /// Propagate the distances of the queued cells to their neighbors.
+    fn run(&mut self) {
+        while let Some((d, i)) = self.queue.pop() {
+            // Cells get queued again when a shorter route is found, skip
+            // the stale entries.
+            if d > self.dists[i] { continue; }
+            // Neighbors reach the goals by stepping into this cell. A goal
+            // that can't be entered doesn't lead anywhere.
+            let c = match self.costs[i] {
+                Some(c) => c,
+                None => continue
+            };
+            let new_dist = d + c;
+            if new_dist >= self.limit { continue; }
+            let pos = self.pos(i);
+            for k in 0..self.dirs.len() {
+                let j = match self.index(pos + self.dirs[k]) {
+                    Some(j) => j,
+                    None => continue
+                };
+                if self.costs[j].is_some() && new_dist < self.dists[j] {
+                    self.dists[j] = new_dist;
+                    self.queue.push(new_dist, j);
+                }
+            }
+        }
+    }
+
+    /// Return the shortest distance from the cell through its neighbors,
+    /// or UNREACHED if the cell can't be entered or has no reached
+    /// neighbors.
+    fn best_entry(&self, idx: usize) -> u32 {
+        if self.costs[idx].is_none() { return UNREACHED; }
+        let pos = self.pos(idx);
+        let mut ret = UNREACHED;
+        for d in self.dirs.iter() {
+            if let Some(j) = self.index(pos + *d) {
+                if let Some(c) = self.costs[j] {
+                    if self.dists[j] != UNREACHED && self.dists[j] + c < ret {
+                        ret = self.dists[j] + c;
+                    }
+                }
+            }
+        }
+        if ret < self.limit { ret } else { UNREACHED }
+    }
+
+    /// Change the cost of entering a cell and update the map to match. Set
+    /// the cost to None to make the cell impassable.
+    pub fn set_cost(&mut self, pos: V2<i32>, cost: Option<u32>) {
+        let i = match self.index(pos) {
+            Some(i) => i,
+            None => return
+        };
+        let old = self.costs[i];
+        if old == cost { return; }
+        self.costs[i] = cost;
+
+        let cheaper = match (old, cost) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(a), Some(b)) => b < a,
+        };
+
+        if cheaper {
+            // Only the cell itself and whatever reaches the goals through
+            // it can get shorter distances.
+            if old.is_none() && !self.goals[i] {
+                self.dists[i] = self.best_entry(i);
+            }
+            if self.dists[i] != UNREACHED {
+                let d = self.dists[i];
+                self.queue.push(d, i);
+                self.run();
+            }
+            return;
+        }
+        // Nothing goes through a cell that isn't reached.
+        if self.dists[i] == UNREACHED { return; }
+
+        // Find the cells whose shortest paths may step into the changed
+        // cell. Those are reached from it along edges where the distance
+        // grows by exactly the cost of the cell stepped into.
+        let mut affected = vec![i];
+        let mut marked: Vec<bool> = self.dists.iter().map(|_| false).collect();
+        marked[i] = true;
+        let mut k = 0;
+        while k < affected.len() {
+            let n = affected[k];
+            let step = if n == i { old.unwrap() } else { self.costs[n].unwrap() };
+            let pos = self.pos(n);
+            for d in self.dirs.iter() {
+                if let Some(j) = self.index(pos + *d) {
+                    if marked[j] || self.goals[j] || self.dists[j] == UNREACHED { continue; }
+                    if self.dists[j] == self.dists[n] + step {
+                        marked[j] = true;
+                        affected.push(j);
+                    }
+                }
+            }
+            k += 1;
+        }
+        // The changed cell keeps its own distance unless it was closed.
+        if self.goals[i] || cost.is_some() { affected.swap_remove(0); }
+
+        // Clear the affected cells and fill them back in from the
+        // unaffected cells around them.
+        for &j in affected.iter() { self.dists[j] = UNREACHED; }
+        for &j in affected.iter() {
+            let d = self.best_entry(j);
+            if d != UNREACHED {
+                self.dists[j] = d;
+                self.queue.push(d, j);
+            }
+        }
+        self.run();
+    }
+
+    /// Iterate the cells covered by the map and their values.
+    pub fn iter<'a>(&'a self) -> GridDijkstraIter<'a> {
+        GridDijkstraIter { map: self, idx: 0 }
+    }
+}
+
+impl DijkstraMap<V2<i32>> for GridDijkstra {
+    fn get(&self, pos: &V2<i32>) -> Option<f32> {
+        match self.index(*pos) {
+            Some(i) if self.dists[i] != UNREACHED => Some(self.dists[i] as f32),
+            _ => None
+        }
+    }
+
+    fn sorted_neighbors(&self, pos: &V2<i32>) -> Vec<V2<i32>> {
+        let mut ret = Vec::new();
+        for d in self.dirs.iter() {
+            let p = *pos + *d;
+            if let Some(i) = self.index(p) {
+                if let Some(c) = self.costs[i] {
+                    if self.dists[i] != UNREACHED { ret.push((self.dists[i] + c, p)); }
+                }
+            }
+        }
+        ret.sort_by(|&(w1, _), &(w2, _)| w1.cmp(&w2));
+        ret.into_iter().map(|(_, p)| p).collect()
+    }
+}
+
+/// Iterator over the cells of a GridDijkstra map and their values.
+pub struct GridDijkstraIter<'a> {
+    map: &'a GridDijkstra,
+    idx: usize,
+}
+
+impl<'a> Iterator for GridDijkstraIter<'a> {
+    type Item = (V2<i32>, f32);
+
+    fn next(&mut self) -> Option<(V2<i32>, f32)> {
+        while self.idx < self.map.dists.len() {
+            let i = self.idx;
+            self.idx += 1;
+            if self.map.dists[i] != UNREACHED {
+                return Some((self.map.pos(i), self.map.dists[i] as f32));
+            }
+        }
+        None
+    }
+}
+
+/// Priority queue for small integer priorities. Items are kept in a bucket
+/// for each priority value, the buckets keep their allocations between
+/// uses.
+struct BucketQueue {
+    buckets: Vec<Vec<usize>>,
+    current: usize,
+    len: usize,
+}
+
+impl BucketQueue {
+    fn new() -> BucketQueue {
+        BucketQueue { buckets: Vec::new(), current: 0, len: 0 }
+    }
+
+    fn push(&mut self, priority: u32, item: usize) {
+        let p = priority as usize;
+        while self.buckets.len() <= p { self.buckets.push(Vec::new()); }
+        self.buckets[p].push(item);
+        if self.len == 0 || p < self.current { self.current = p; }
+        self.len += 1;
+    }
+
+    /// Pop an item with the lowest priority.
+    fn pop(&mut self) -> Option<(u32, usize)> {
+        if self.len == 0 { return None; }
+        while self.buckets[self.current].is_empty() { self.current += 1; }
+        self.len -= 1;
+        let p = self.current;
+        self.buckets[p].pop().map(|i| (p as u32, i))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use geom::{V2, Rect};
+    use dijkstra::{DijkstraNode, DijkstraMap};
+
+    static DIRS: [V2<i32>; 4] = [V2(0, -1), V2(1, 0), V2(0, 1), V2(-1, 0)];
+
+    static MAP: [&'static str; 5] = [
+        "......~.",
+        ".####.~.",
+        "....#...",
+        ".##.#.#.",
+        "...~....",
+    ];
+
+    fn cost(p: V2<i32>) -> Option<u32> {
+        match MAP[p.1 as usize].as_bytes()[p.0 as usize] {
+            b'#' => None,
+            b'~' => Some(3),
+            _ => Some(1),
+        }
+    }
+
+    #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+    struct Cell(V2<i32>);
+
+    impl DijkstraNode for Cell {
+        fn neighbors(&self) -> Vec<Cell> {
+            let Cell(p) = *self;
+            DIRS.iter().map(|&d| Cell(p + d)).collect()
+        }
+
+        fn cost(&self, other: &Cell) -> u32 { cost(other.0).unwrap() }
+    }
+
+    #[test]
+    fn test_grid_dijkstra() {
+        use super::GridDijkstra;
+        use dijkstra::Dijkstra;
+
+        let area = Rect(V2(0, 0), V2(8, 5));
+        let goals = vec![V2(0, 2), V2(7, 0)];
+        let valid = |c: &Cell| area.contains(&c.0) && cost(c.0).is_some();
+        let grid = GridDijkstra::new(area, &DIRS, &goals, cost, 100);
+        let hash = Dijkstra::new(goals.iter().map(|&p| Cell(p)).collect(), &valid, 100);
+
+        for p in area.iter() {
+            assert_eq!(hash.get(&Cell(p)), grid.get(&p));
+        }
+        assert_eq!(vec![V2(6, 2), V2(7, 2), V2(7, 1), V2(7, 0)], grid.path_from(&V2(5, 2)));
+
+        let limited = GridDijkstra::new(area, &DIRS, &goals, cost, 3);
+        assert_eq!(Some(2.0), limited.get(&V2(2, 2)));
+        assert_eq!(None, limited.get(&V2(3, 2)));
+    }
+
+    #[test]
+    fn test_entry_costs() {
+        use super::GridDijkstra;
+
+        // Digits are the costs of entering the cells.
+        static COSTS: [&'static str; 3] = [
+            "129",
+            "1#1",
+            "111",
+        ];
+        let cost = |p: V2<i32>| (COSTS[p.1 as usize].as_bytes()[p.0 as usize] as char).to_digit(10);
+        let area = Rect(V2(0, 0), V2(3, 3));
+        let grid = GridDijkstra::new(area, &DIRS, &[V2(0, 0)], cost, 100);
+
+        // Distances count the cells stepped into on the way to the goal,
+        // leaving an expensive cell is cheap.
+        let expected = [
+            Some(0.0), Some(1.0), Some(3.0),
+            Some(1.0), None,      Some(5.0),
+            Some(2.0), Some(3.0), Some(4.0)];
+        for (p, &d) in area.iter().zip(expected.iter()) {
+            assert_eq!(d, grid.get(&p));
+        }
+        assert_eq!(8, grid.iter().count());
+        assert_eq!(19.0, grid.iter().fold(0.0, |a, (_, d)| a + d));
+
+        // The path goes around the expensive cell, though it is closer to
+        // the goal.
+        assert_eq!(vec![V2(2, 2), V2(1, 2), V2(0, 2), V2(0, 1), V2(0, 0)],
+                   grid.path_from(&V2(2, 1)));
+    }
+
+    #[test]
+    fn test_set_cost() {
+        use super::GridDijkstra;
+
+        let area = Rect(V2(0, 0), V2(8, 5));
+        let goals = vec![V2(0, 0)];
+        let mut grid = GridDijkstra::new(area, &DIRS, &goals, cost, 100);
+
+        // Block cells, then open them up again with different costs, and
+        // check the map against one computed from scratch every time.
+        let changes = [(V2(5, 1), None), (V2(3, 2), None), (V2(5, 1), Some(1)),
+                       (V2(4, 1), Some(1)), (V2(3, 2), Some(5)), (V2(0, 1), None),
+                       (V2(0, 0), Some(4)), (V2(6, 0), Some(1)), (V2(0, 0), None),
+                       (V2(0, 0), Some(1)), (V2(1, 0), None), (V2(1, 0), Some(2)),
+                       (V2(1, 0), None)];
+        let mut costs: Vec<Option<u32>> = area.iter().map(|p| cost(p)).collect();
+        for &(pos, c) in changes.iter() {
+            grid.set_cost(pos, c);
+            costs[(pos.0 + pos.1 * 8) as usize] = c;
+            let fresh = GridDijkstra::new(area, &DIRS, &goals, |p| costs[(p.0 + p.1 * 8) as usize], 100);
+            for p in area.iter() {
+                assert_eq!(fresh.get(&p), grid.get(&p));
+            }
+        }
+        // Walled in.
+        assert_eq!(None, grid.get(&V2(7, 4)));
+    }
+}
@@ -0,0 +1,281 @@
+/*!
+Hex grid coordinates.
+
+Hexes use axial coordinates, where the q axis points east and the r axis
+points southeast on the screen for pointy-topped hexes. The third cube
+coordinate is s = -q - r. See http://www.redblobgames.com/grids/hexagons/
+for a thorough explanation.
+ */
+
+use std::num::Float;
+use std::f32::consts::PI;
+use std::ops::{Add, Sub, Mul, Neg};
+use geom::V2;
+use dijkstra::DijkstraNode;
+
+/// A hex cell in axial coordinates.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Hex {
+    pub q: i32,
+    pub r: i32,
+}
+
+/// The six neighbor directions, counterclockwise on the screen starting
+/// from east for pointy-topped hexes.
+pub static DIRECTIONS: [Hex; 6] = [
+    Hex { q: 1, r: 0 }, Hex { q: 1, r: -1 }, Hex { q: 0, r: -1 },
+    Hex { q: -1, r: 0 }, Hex { q: -1, r: 1 }, Hex { q: 0, r: 1 }];
+
+impl Hex {
+    pub fn new(q: i32, r: i32) -> Hex { Hex { q: q, r: r } }
+
+    /// Build a hex from cube coordinates, which must sum to zero.
+    pub fn from_cube(q: i32, r: i32, s: i32) -> Hex {
+        assert!(q + r + s == 0, "Cube coordinates must sum to zero");
+        Hex::new(q, r)
+    }
+
+    /// The third cube coordinate.
+    pub fn s(self) -> i32 { -self.q - self.r }
+
+    /// Return the cube coordinates (q, r, s).
+    pub fn cube(self) -> (i32, i32, i32) { (self.q, self.r, self.s()) }
+
+    /// Convert from a vector with the axial coordinates, the form the hex
+    /// topology of fov uses.
+    pub fn from_v2(v: V2<i32>) -> Hex { Hex::new(v.0, v.1) }
+
+    pub fn to_v2(self) -> V2<i32> { V2(self.q, self.r) }
+
+    /// Return the neighbor in one of the six directions.
+    pub fn neighbor(self, dir: usize) -> Hex { self + DIRECTIONS[dir % 6] }
+
+    /// Distance from the origin in hex steps.
+    pub fn len(self) -> i32 {
+        (self.q.abs() + self.r.abs() + self.s().abs()) / 2
+    }
+
+    /// Distance between two hexes in hex steps.
+    pub fn distance(self, other: Hex) -> i32 { (self - other).len() }
+
+    /// Rotate around the origin by steps of 60 degrees, in the same
+    /// direction DIRECTIONS go. Negative steps rotate the other way.
+    pub fn rotate(self, steps: i32) -> Hex {
+        let mut ret = self;
+        for _ in 0..((steps % 6 + 6) % 6) {
+            ret = Hex::new(-ret.s(), -ret.q);
+        }
+        ret
+    }
+
+    /// Return the hexes at exactly radius steps from this hex.
+    pub fn ring(self, radius: i32) -> Vec<Hex> {
+        if radius == 0 { return vec![self]; }
+        let mut ret = Vec::new();
+        let mut pos = self + DIRECTIONS[4] * radius;
+        for i in 0..6 {
+            for _ in 0..radius {
+                ret.push(pos);
+                pos = pos + DIRECTIONS[i];
+            }
+        }
+        ret
+    }
+
+    /// Return the hexes up to radius steps from this hex, going outwards
+    /// ring by ring starting from the hex itself.
+    pub fn spiral(self, radius: i32) -> Vec<Hex> {
+        let mut ret = Vec::new();
+        for r in 0..(radius + 1) { ret.push_all(&self.ring(r)); }
+        ret
+    }
+
+    /// Return the hexes on a line between two hexes, including both ends.
+    pub fn line(self, other: Hex) -> Vec<Hex> {
+        let n = self.distance(other);
+        if n == 0 { return vec![self]; }
+        (0..(n + 1)).map(|i| {
+            let t = i as f32 / n as f32;
+            // Nudge the points off the hex edges so that they round
+            // consistently.
+            round(self.q as f32 + (other.q - self.q) as f32 * t + 1e-4,
+                  self.r as f32 + (other.r - self.r) as f32 * t + 2e-4)
+        }).collect()
+    }
+}
+
+/// Return the hex that contains a point in fractional axial coordinates.
+pub fn round(q: f32, r: f32) -> Hex {
+    let s = -q - r;
+    let (rq, rr, rs) = (q.round(), r.round(), s.round());
+    let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+    // Fix the coordinate that was rounded the most to keep the sum zero.
+    if dq > dr && dq > ds {
+        Hex::new(-(rr as i32) - rs as i32, rr as i32)
+    } else if dr > ds {
+        Hex::new(rq as i32, -(rq as i32) - rs as i32)
+    } else {
+        Hex::new(rq as i32, rr as i32)
+    }
+}
+
+impl Add<Hex> for Hex {
+    type Output = Hex;
+    fn add(self, rhs: Hex) -> Hex { Hex::new(self.q + rhs.q, self.r + rhs.r) }
+}
+
+impl Sub<Hex> for Hex {
+    type Output = Hex;
+    fn sub(self, rhs: Hex) -> Hex { Hex::new(self.q - rhs.q, self.r - rhs.r) }
+}
+
+impl Mul<i32> for Hex {
+    type Output = Hex;
+    fn mul(self, rhs: i32) -> Hex { Hex::new(self.q * rhs, self.r * rhs) }
+}
+
+impl Neg for Hex {
+    type Output = Hex;
+    fn neg(self) -> Hex { Hex::new(-self.q, -self.r) }
+}
+
+impl DijkstraNode for Hex {
+    fn neighbors(&self) -> Vec<Hex> {
+        DIRECTIONS.iter().map(|&d| *self + d).collect()
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Orientation {
+    /// Hexes have a corner pointing up and form horizontal rows.
+    Pointy,
+    /// Hexes have a flat top and form vertical columns.
+    Flat,
+}
+
+/// Mapping between hexes and screen pixels.
+#[derive(Copy, Clone, Debug)]
+pub struct Layout {
+    pub orientation: Orientation,
+    /// Distance from the hex center to its corners horizontally and
+    /// vertically. Different values squash the hexes.
+    pub size: V2<f32>,
+    /// Screen position of the center of the origin hex.
+    pub origin: V2<f32>,
+}
+
+impl Layout {
+    pub fn new(orientation: Orientation, size: V2<f32>, origin: V2<f32>) -> Layout {
+        Layout {
+            orientation: orientation,
+            size: size,
+            origin: origin,
+        }
+    }
+
+    /// Screen position of the center of a hex.
+    pub fn to_pixel(&self, hex: Hex) -> V2<f32> {
+        let sqrt3 = 3.0f32.sqrt();
+        let (q, r) = (hex.q as f32, hex.r as f32);
+        let (x, y) = match self.orientation {
+            Orientation::Pointy => (sqrt3 * q + sqrt3 / 2.0 * r, 1.5 * r),
+            Orientation::Flat => (1.5 * q, sqrt3 / 2.0 * q + sqrt3 * r),
+        };
+        self.origin + V2(x * self.size.0, y * self.size.1)
+    }
+
+    /// The hex containing a screen position.
+    pub fn from_pixel(&self, pos: V2<f32>) -> Hex {
+        let sqrt3 = 3.0f32.sqrt();
+        let p = pos - self.origin;
+        let (x, y) = (p.0 / self.size.0, p.1 / self.size.1);
+        match self.orientation {
+            Orientation::Pointy => round(sqrt3 / 3.0 * x - y / 3.0, 2.0 / 3.0 * y),
+            Orientation::Flat => round(2.0 / 3.0 * x, -x / 3.0 + sqrt3 / 3.0 * y),
+        }
+    }
+
+    /// Screen positions of the six corners of a hex.
+    pub fn corners(&self, hex: Hex) -> Vec<V2<f32>> {
+        let center = self.to_pixel(hex);
+        let start = match self.orientation {
+            Orientation::Pointy => 0.5,
+            Orientation::Flat => 0.0,
+        };
+        (0..6).map(|i| {
+            let angle = (start + i as f32) * PI / 3.0;
+            center + V2(angle.cos() * self.size.0, angle.sin() * self.size.1)
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Hex;
+
+    #[test]
+    fn test_hex() {
+        use super::DIRECTIONS;
+        use geom::V2;
+
+        let a = Hex::new(2, -1);
+        assert_eq!((2, -1, -1), a.cube());
+        assert_eq!(a, Hex::from_cube(2, -1, -1));
+        assert_eq!(a, Hex::from_v2(a.to_v2()));
+        assert_eq!(V2(2, -1), a.to_v2());
+        assert_eq!(2, a.len());
+        assert_eq!(3, a.distance(Hex::new(-1, 2)));
+
+        for i in 0..6 {
+            assert_eq!(DIRECTIONS[(i + 1) % 6], DIRECTIONS[i].rotate(1));
+            assert_eq!(DIRECTIONS[(i + 4) % 6], DIRECTIONS[i].rotate(-2));
+        }
+        assert_eq!(a, a.rotate(6));
+
+        assert_eq!(vec![a], a.ring(0));
+        for r in 1..4 {
+            let ring = a.ring(r);
+            assert_eq!(6 * r as usize, ring.len());
+            assert!(ring.iter().all(|h| h.distance(a) == r));
+        }
+        assert_eq!(1 + 3 * 3 * 4, a.spiral(3).len());
+
+        let line = Hex::new(0, 0).line(Hex::new(3, -3));
+        assert_eq!(vec![Hex::new(0, 0), Hex::new(1, -1), Hex::new(2, -2), Hex::new(3, -3)], line);
+        let line = a.line(Hex::new(-2, 3));
+        assert_eq!(5, line.len());
+        for i in 1..line.len() { assert_eq!(1, line[i - 1].distance(line[i])); }
+    }
+
+    #[test]
+    fn test_layout() {
+        use super::{Layout, Orientation};
+        use geom::V2;
+
+        for &o in [Orientation::Pointy, Orientation::Flat].iter() {
+            let layout = Layout::new(o, V2(10.0, 8.0), V2(100.0, 50.0));
+            assert_eq!(V2(100.0, 50.0), layout.to_pixel(Hex::new(0, 0)));
+            for h in Hex::new(0, 0).spiral(3).into_iter() {
+                let p = layout.to_pixel(h);
+                assert_eq!(h, layout.from_pixel(p));
+                // Points well inside the hex land in it too.
+                for c in layout.corners(h).into_iter() {
+                    assert_eq!(h, layout.from_pixel(p + (c - p) * 0.9));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_hex_dijkstra() {
+        use dijkstra::{Dijkstra, DijkstraMap};
+
+        let wall = |h: &Hex| h.q == 1 && h.r > -2;
+        let map = Dijkstra::new(vec![Hex::new(3, 0)], |h| h.len() <= 4 && !wall(h), 100);
+        let path = map.path_from(&Hex::new(-1, 0));
+        assert_eq!(Some(&Hex::new(3, 0)), path.last());
+        assert!(path.iter().all(|h| !wall(h)));
+        // Around the end of the wall.
+        assert_eq!(6, path.len());
+    }
+}
@@ -0,0 +1,295 @@
+use std::cmp::{min, max};
+use std::collections::HashMap;
+use geom::{V2, Rect};
+use astar::{search, octile};
+use jps::grid_neighbors;
+
+/// Hierarchical pathfinding (HPA*) abstraction of a large 8-connected grid.
+///
+/// The grid is split into square clusters. Open cells on both sides of a
+/// cluster border are linked as entrances, and the shortest paths between
+/// the entrances of each cluster are precomputed. Paths are first searched
+/// over the entrance graph and then refined into cells inside the clusters
+/// along the way. The paths are near optimal, but not always the shortest
+/// possible ones. Step costs are the same as for grid_neighbors.
+pub struct Hpa {
+    area: Rect<i32>,
+    cluster_size: i32,
+    /// Number of clusters along each axis.
+    clusters: V2<i32>,
+    /// Entrance cell pairs on the border between two neighboring clusters,
+    /// keyed by the indices of the clusters with the smaller one first.
+    borders: HashMap<(usize, usize), Vec<(V2<i32>, V2<i32>)>>,
+    /// Path costs between the entrance cells inside each cluster.
+    edges: Vec<HashMap<V2<i32>, Vec<(V2<i32>, u32)>>>,
+}
+
+impl Hpa {
+    /// Build the abstraction for an area of a grid.
+    pub fn new<F: Fn(V2<i32>) -> bool>(area: Rect<i32>, cluster_size: i32, is_open: &F) -> Hpa {
+        assert!(cluster_size > 0);
+        let clusters = V2(((area.1).0 + cluster_size - 1) / cluster_size,
+                          ((area.1).1 + cluster_size - 1) / cluster_size);
+        let n = (clusters.0 * clusters.1) as usize;
+        let mut ret = Hpa {
+            area: area,
+            cluster_size: cluster_size,
+            clusters: clusters,
+            borders: HashMap::new(),
+            edges: (0..n).map(|_| HashMap::new()).collect(),
+        };
+
+        for i in 0..n {
+            for j in ret.cluster_neighbors(i).into_iter() {
+                if i < j { ret.build_border(i, j, is_open); }
+            }
+        }
+        for i in 0..n { ret.build_edges(i, is_open); }
+        ret
+    }
+
+    /// Update the abstraction after the open cells in the cluster
+    /// containing pos have changed.
+    pub fn update<F: Fn(V2<i32>) -> bool>(&mut self, pos: V2<i32>, is_open: &F) {
+        let i = match self.cluster(pos) {
+            Some(i) => i,
+            None => return
+        };
+        let neighbors = self.cluster_neighbors(i);
+        for &j in neighbors.iter() {
+            self.build_border(min(i, j), max(i, j), is_open);
+        }
+        self.build_edges(i, is_open);
+        for &j in neighbors.iter() { self.build_edges(j, is_open); }
+    }
+
+    /// Find a path between two cells. The search over the entrance graph
+    /// gives up after expanding max_nodes entrances.
+    ///
+    /// Returns every cell of the path including the start and goal cells and
+    /// its total cost, or None if no path was found.
+    pub fn find_path<F: Fn(V2<i32>) -> bool>(&self, start: V2<i32>, goal: V2<i32>, is_open: &F,
+                                             max_nodes: usize) -> Option<(Vec<V2<i32>>, u32)> {
+        let (start_cluster, goal_cluster) = match (self.cluster(start), self.cluster(goal)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return None
+        };
+        if start_cluster == goal_cluster {
+            if let Some(ret) = self.local_path(start_cluster, start, goal, is_open) {
+                return Some(ret);
+            }
+        }
+
+        // Temporarily link the start and goal cells to the entrances of
+        // their clusters.
+        let from_start: Vec<(V2<i32>, u32)> = self.entrances(start_cluster).into_iter()
+            .filter_map(|e| self.local_path(start_cluster, start, e, is_open).map(|(_, c)| (e, c)))
+            .collect();
+        let to_goal: HashMap<V2<i32>, u32> = self.entrances(goal_cluster).into_iter()
+            .filter_map(|e| self.local_path(goal_cluster, e, goal, is_open).map(|(_, c)| (e, c)))
+            .collect();
+
+        let (nodes, cost) = match search(start, goal, octile, |&pos, _| {
+            let mut ret = self.abstract_edges(pos);
+            if pos == start { ret.push_all(&from_start); }
+            if let Some(&c) = to_goal.get(&pos) { ret.push((goal, c)); }
+            ret
+        }, max_nodes) {
+            Some(x) => x,
+            None => return None
+        };
+
+        // Refine the path between entrances into grid cells.
+        let mut path = vec![start];
+        for i in 1..nodes.len() {
+            let (a, b) = (nodes[i - 1], nodes[i]);
+            let c = self.cluster(a).unwrap();
+            if Some(c) == self.cluster(b) {
+                // Fails if the map has changed without an update.
+                let (cells, _) = match self.local_path(c, a, b, is_open) {
+                    Some(x) => x,
+                    None => return None
+                };
+                path.push_all(&cells[1..]);
+            } else {
+                path.push(b);
+            }
+        }
+        Some((path, cost))
+    }
+
+    fn cluster(&self, pos: V2<i32>) -> Option<usize> {
+        if !self.area.contains(&pos) { return None; }
+        let p = pos - self.area.0;
+        Some((p.0 / self.cluster_size + p.1 / self.cluster_size * self.clusters.0) as usize)
+    }
+
+    fn cluster_rect(&self, idx: usize) -> Rect<i32> {
+        let c = V2(idx as i32 % self.clusters.0, idx as i32 / self.clusters.0);
+        let mn = self.area.0 + V2(c.0 * self.cluster_size, c.1 * self.cluster_size);
+        let mx = self.area.mx();
+        let mx = V2(min(mn.0 + self.cluster_size, mx.0), min(mn.1 + self.cluster_size, mx.1));
+        Rect(mn, mx - mn)
+    }
+
+    /// Indices of the clusters next to a cluster on the four sides.
+    fn cluster_neighbors(&self, idx: usize) -> Vec<usize> {
+        let c = V2(idx as i32 % self.clusters.0, idx as i32 / self.clusters.0);
+        [V2(0, -1), V2(1, 0), V2(0, 1), V2(-1, 0)].iter()
+            .map(|&d| c + d)
+            .filter(|p| p.0 >= 0 && p.1 >= 0 && p.0 < self.clusters.0 && p.1 < self.clusters.1)
+            .map(|p| (p.0 + p.1 * self.clusters.0) as usize)
+            .collect()
+    }
+
+    /// Find the entrances on the border between cluster a and the cluster
+    /// b to the east or south of it.
+    fn build_border<F: Fn(V2<i32>) -> bool>(&mut self, a: usize, b: usize, is_open: &F) {
+        let (ra, rb) = (self.cluster_rect(a), self.cluster_rect(b));
+        // The cells along the border on the side of a and the step across.
+        let (cells, step): (Vec<V2<i32>>, V2<i32>) = if (rb.0).0 > (ra.0).0 {
+            (((ra.0).1..ra.mx().1).map(|y| V2(ra.mx().0 - 1, y)).collect(), V2(1, 0))
+        } else {
+            (((ra.0).0..ra.mx().0).map(|x| V2(x, ra.mx().1 - 1)).collect(), V2(0, 1))
+        };
+
+        // Each run of open cell pairs along the border gets an entrance in
+        // the middle, long runs get one at both ends instead.
+        let mut entrances = Vec::new();
+        let mut run: Vec<V2<i32>> = Vec::new();
+        for i in 0..(cells.len() + 1) {
+            if i < cells.len() && is_open(cells[i]) && is_open(cells[i] + step) {
+                run.push(cells[i]);
+                continue;
+            }
+            if run.len() >= 6 {
+                entrances.push(run[0]);
+                entrances.push(run[run.len() - 1]);
+            } else if run.len() > 0 {
+                entrances.push(run[run.len() / 2]);
+            }
+            run.clear();
+        }
+        self.borders.insert((a, b), entrances.into_iter().map(|p| (p, p + step)).collect());
+    }
+
+    /// The entrance cells inside a cluster.
+    fn entrances(&self, idx: usize) -> Vec<V2<i32>> {
+        let mut ret = Vec::new();
+        for j in self.cluster_neighbors(idx).into_iter() {
+            let key = if idx < j { (idx, j) } else { (j, idx) };
+            if let Some(pairs) = self.borders.get(&key) {
+                for &(p, q) in pairs.iter() {
+                    let e = if idx < j { p } else { q };
+                    if !ret.contains(&e) { ret.push(e); }
+                }
+            }
+        }
+        ret
+    }
+
+    /// Compute the path costs between the entrances of a cluster.
+    fn build_edges<F: Fn(V2<i32>) -> bool>(&mut self, idx: usize, is_open: &F) {
+        let entrances = self.entrances(idx);
+        let mut edges = HashMap::new();
+        for &a in entrances.iter() {
+            let mut links = Vec::new();
+            for &b in entrances.iter() {
+                if a == b { continue; }
+                if let Some((_, c)) = self.local_path(idx, a, b, is_open) { links.push((b, c)); }
+            }
+            edges.insert(a, links);
+        }
+        self.edges[idx] = edges;
+    }
+
+    /// The entrance graph neighbors of a cell, the other entrances of its
+    /// cluster and the entrances it's linked to across cluster borders.
+    fn abstract_edges(&self, pos: V2<i32>) -> Vec<(V2<i32>, u32)> {
+        let idx = match self.cluster(pos) {
+            Some(i) => i,
+            None => return Vec::new()
+        };
+        let mut ret = self.edges[idx].get(&pos).map_or(Vec::new(), |e| e.clone());
+        for j in self.cluster_neighbors(idx).into_iter() {
+            let key = if idx < j { (idx, j) } else { (j, idx) };
+            if let Some(pairs) = self.borders.get(&key) {
+                for &(p, q) in pairs.iter() {
+                    if p == pos { ret.push((q, 10)); }
+                    if q == pos { ret.push((p, 10)); }
+                }
+            }
+        }
+        ret
+    }
+
+    /// Find a path between two cells that stays inside a cluster.
+    fn local_path<F: Fn(V2<i32>) -> bool>(&self, idx: usize, a: V2<i32>, b: V2<i32>, is_open: &F)
+        -> Option<(Vec<V2<i32>>, u32)> {
+        let rect = self.cluster_rect(idx);
+        let inside = |p: V2<i32>| rect.contains(&p) && is_open(p);
+        search(a, b, octile, |&p, _| grid_neighbors(p, &inside), ((rect.1).0 * (rect.1).1) as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use geom::{V2, Rect};
+
+    static MAP: [&'static str; 12] = [
+        "............",
+        ".....#......",
+        ".....#..###.",
+        ".....#....#.",
+        "######....#.",
+        "..........#.",
+        ".#####....#.",
+        ".....#......",
+        ".....#......",
+        ".....#######",
+        ".....#......",
+        "............",
+    ];
+
+    #[test]
+    fn test_hpa() {
+        use super::Hpa;
+        use astar::{search, octile};
+        use jps::grid_neighbors;
+
+        let walls: RefCell<Vec<V2<i32>>> = RefCell::new(Rect(V2(0, 0), V2(12, 12)).iter()
+            .filter(|p| MAP[p.1 as usize].as_bytes()[p.0 as usize] == b'#').collect());
+        let is_open = |p: V2<i32>| {
+            p.0 >= 0 && p.1 >= 0 && p.0 < 12 && p.1 < 12 && !walls.borrow().contains(&p)
+        };
+
+        let mut hpa = Hpa::new(Rect(V2(0, 0), V2(12, 12)), 4, &is_open);
+        let check = |hpa: &Hpa, start, goal| {
+            let (path, cost) = hpa.find_path(start, goal, &is_open, 1000).unwrap();
+            let (_, best) = search(start, goal, octile, |&p, _| grid_neighbors(p, &is_open), 1000).unwrap();
+            assert_eq!(start, path[0]);
+            assert_eq!(goal, path[path.len() - 1]);
+            for i in 1..path.len() {
+                assert!(grid_neighbors(path[i - 1], &is_open).iter().any(|&(p, _)| p == path[i]));
+            }
+            // Near optimal.
+            assert!(cost >= best && cost <= best * 3 / 2);
+        };
+
+        check(&hpa, V2(0, 0), V2(11, 11));
+        check(&hpa, V2(2, 8), V2(0, 3));
+        check(&hpa, V2(8, 1), V2(9, 3));
+        check(&hpa, V2(6, 6), V2(6, 6));
+        assert!(hpa.find_path(V2(0, 0), V2(5, 2), &is_open, 1000).is_none());
+
+        // Wall off the bottom left corner.
+        walls.borrow_mut().push(V2(0, 5));
+        hpa.update(V2(0, 5), &is_open);
+        assert!(hpa.find_path(V2(0, 0), V2(2, 8), &is_open, 1000).is_none());
+
+        walls.borrow_mut().pop();
+        hpa.update(V2(0, 5), &is_open);
+        check(&hpa, V2(0, 0), V2(2, 8));
+    }
+}
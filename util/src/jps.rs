@@ -0,0 +1,177 @@
+use geom::V2;
+use astar::{search, octile};
+
+/// Unit vectors for the eight grid directions.
+static DIRS: [V2<i32>; 8] = [
+    V2(0, -1), V2(1, -1), V2(1, 0), V2(1, 1),
+    V2(0, 1), V2(-1, 1), V2(-1, 0), V2(-1, -1)];
+
+/// List the open neighbors of an 8-connected grid cell with their octile
+/// step costs. Diagonal steps can't cut corners, both of the orthogonal
+/// cells next to the step must be open.
+pub fn grid_neighbors<F: Fn(V2<i32>) -> bool>(pos: V2<i32>, is_open: &F) -> Vec<(V2<i32>, u32)> {
+    let mut ret = Vec::new();
+    for &d in DIRS.iter() {
+        if can_step(pos, d, is_open) {
+            ret.push((pos + d, if d.0 != 0 && d.1 != 0 { 14 } else { 10 }));
+        }
+    }
+    ret
+}
+
+fn can_step<F: Fn(V2<i32>) -> bool>(pos: V2<i32>, dir: V2<i32>, is_open: &F) -> bool {
+    is_open(pos + dir) && (dir.0 == 0 || dir.1 == 0 ||
+                           (is_open(pos + V2(dir.0, 0)) && is_open(pos + V2(0, dir.1))))
+}
+
+fn signum(x: i32) -> i32 { if x > 0 { 1 } else if x < 0 { -1 } else { 0 } }
+
+/// Find the lowest cost path between two cells of a uniform cost
+/// 8-connected grid with jump point search.
+///
+/// Jump point search skips over the open stretches of the grid where the
+/// path has only one sensible way to continue, and only puts the cells
+/// where the path may turn into the A* open set. Uses the same step costs
+/// as grid_neighbors, 10 for orthogonal and 14 for diagonal steps. The
+/// search gives up after expanding max_nodes jump points.
+///
+/// Returns every cell of the path including the start and goal cells and
+/// its total cost, or None if no path was found.
+pub fn jps<F: Fn(V2<i32>) -> bool>(start: V2<i32>, goal: V2<i32>, is_open: F, max_nodes: usize)
+    -> Option<(Vec<V2<i32>>, u32)> {
+    let ret = search(start, goal, octile, |&pos, parent| {
+        let mut ret = Vec::new();
+        for d in pruned_dirs(pos, parent.map(|&p| p), &is_open).into_iter() {
+            if let Some(p) = jump(pos, d, goal, &is_open) {
+                ret.push((p, octile(&pos, &p)));
+            }
+        }
+        ret
+    }, max_nodes);
+
+    // Fill in the straight stretches between the jump points.
+    ret.map(|(points, cost)| {
+        let mut path = vec![points[0]];
+        for i in 1..points.len() {
+            let (mut pos, end) = (points[i - 1], points[i]);
+            while pos != end {
+                pos = pos + V2(signum(end.0 - pos.0), signum(end.1 - pos.1));
+                path.push(pos);
+            }
+        }
+        (path, cost)
+    })
+}
+
+/// The directions worth continuing to from a cell when arriving from
+/// parent. Cells that are reached as cheaply without going through this
+/// cell are left out.
+fn pruned_dirs<F: Fn(V2<i32>) -> bool>(pos: V2<i32>, parent: Option<V2<i32>>, is_open: &F) -> Vec<V2<i32>> {
+    let parent = match parent {
+        Some(p) => p,
+        None => return DIRS.iter().cloned().filter(|&d| can_step(pos, d, is_open)).collect()
+    };
+    let V2(x, y) = pos;
+    let (dx, dy) = (signum(x - parent.0), signum(y - parent.1));
+    let mut ret = Vec::new();
+
+    if dx != 0 && dy != 0 {
+        let (vertical, horizontal) = (is_open(V2(x, y + dy)), is_open(V2(x + dx, y)));
+        if vertical { ret.push(V2(0, dy)); }
+        if horizontal { ret.push(V2(dx, 0)); }
+        if vertical && horizontal { ret.push(V2(dx, dy)); }
+    } else if dx != 0 {
+        let (next, up, down) = (is_open(V2(x + dx, y)), is_open(V2(x, y - 1)), is_open(V2(x, y + 1)));
+        if next {
+            ret.push(V2(dx, 0));
+            if up { ret.push(V2(dx, -1)); }
+            if down { ret.push(V2(dx, 1)); }
+        }
+        if up { ret.push(V2(0, -1)); }
+        if down { ret.push(V2(0, 1)); }
+    } else {
+        let (next, left, right) = (is_open(V2(x, y + dy)), is_open(V2(x - 1, y)), is_open(V2(x + 1, y)));
+        if next {
+            ret.push(V2(0, dy));
+            if left { ret.push(V2(-1, dy)); }
+            if right { ret.push(V2(1, dy)); }
+        }
+        if left { ret.push(V2(-1, 0)); }
+        if right { ret.push(V2(1, 0)); }
+    }
+    ret
+}
+
+/// Move from pos in dir until reaching a jump point. A jump point is the
+/// goal or a cell that has a neighbor that can only be reached optimally
+/// through it. Returns None if the move runs into a wall first.
+fn jump<F: Fn(V2<i32>) -> bool>(pos: V2<i32>, dir: V2<i32>, goal: V2<i32>, is_open: &F) -> Option<V2<i32>> {
+    let V2(dx, dy) = dir;
+    let mut pos = pos;
+    loop {
+        if !can_step(pos, dir, is_open) { return None; }
+        pos = pos + dir;
+        if pos == goal { return Some(pos); }
+        let V2(x, y) = pos;
+
+        if dx != 0 && dy != 0 {
+            // Diagonal moves stop where a straight move branching off them
+            // would find a jump point.
+            if jump(pos, V2(dx, 0), goal, is_open).is_some() ||
+                jump(pos, V2(0, dy), goal, is_open).is_some() {
+                return Some(pos);
+            }
+        } else if dx != 0 {
+            if (is_open(V2(x, y - 1)) && !is_open(V2(x - dx, y - 1))) ||
+                (is_open(V2(x, y + 1)) && !is_open(V2(x - dx, y + 1))) {
+                return Some(pos);
+            }
+        } else {
+            if (is_open(V2(x - 1, y)) && !is_open(V2(x - 1, y - dy))) ||
+                (is_open(V2(x + 1, y)) && !is_open(V2(x + 1, y - dy))) {
+                return Some(pos);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use geom::V2;
+
+    static MAP: [&'static str; 8] = [
+        "..........",
+        "...#......",
+        "...#..###.",
+        "...#....#.",
+        "...####.#.",
+        "........#.",
+        ".#####..#.",
+        "..........",
+    ];
+
+    fn is_open(p: V2<i32>) -> bool {
+        p.0 >= 0 && p.1 >= 0 && p.0 < 10 && p.1 < 8 && MAP[p.1 as usize].as_bytes()[p.0 as usize] == b'.'
+    }
+
+    #[test]
+    fn test_jps() {
+        use super::{jps, grid_neighbors};
+        use astar::{search, octile};
+
+        for &(start, goal) in [(V2(0, 0), V2(9, 7)), (V2(5, 3), V2(0, 7)), (V2(4, 1), V2(9, 0)),
+                               (V2(2, 5), V2(7, 3)), (V2(6, 3), V2(6, 3))].iter() {
+            let (path, cost) = jps(start, goal, is_open, 1000).unwrap();
+            let (_, expected) = search(start, goal, octile, |&p, _| grid_neighbors(p, &is_open), 1000).unwrap();
+            assert_eq!(expected, cost);
+            assert_eq!(start, path[0]);
+            assert_eq!(goal, path[path.len() - 1]);
+            // The path is made of single open steps.
+            for i in 1..path.len() {
+                assert!(grid_neighbors(path[i - 1], &is_open).iter().any(|&(p, _)| p == path[i]));
+            }
+        }
+
+        assert_eq!(None, jps(V2(0, 0), V2(3, 2), is_open, 1000));
+    }
+}
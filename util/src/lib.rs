@@ -21,19 +21,37 @@ pub use rgb::{Rgb, Rgba};
 pub use geom::{V2, V3, Rect, RectIter};
 pub use img::{color_key};
 pub use atlas::{AtlasBuilder, Atlas, AtlasItem};
-pub use dijkstra::{DijkstraNode, Dijkstra};
+pub use dijkstra::{DijkstraNode, DijkstraMap, Dijkstra};
+pub use astar::{astar, manhattan, chebyshev, octile};
+pub use grid_dijkstra::{GridDijkstra, GridDijkstraIter};
+pub use jps::jps;
+pub use hpa::Hpa;
 pub use encode_rng::{EncodeRng};
 
+mod astar;
 mod atlas;
 mod dijkstra;
 mod geom;
+mod grid_dijkstra;
+mod hpa;
 mod encode_rng;
 mod img;
+mod jps;
 mod primitive;
 mod rgb;
 
+pub mod autotile;
+pub mod bmfont;
 pub mod color;
+pub mod fov;
+pub mod hex;
+pub mod locale;
+pub mod mapgen;
+pub mod noise;
+pub mod poisson;
 pub mod text;
+pub mod tiled;
+pub mod tilemap;
 pub mod timing;
 pub mod vorud;
 
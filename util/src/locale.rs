@@ -0,0 +1,341 @@
+/*!
+Localized string tables.
+
+A catalog holds the user-visible strings of one language. Catalog files are
+UTF-8 text with one `key = text` entry per line. Empty lines and lines
+starting with `#` are ignored. The texts can contain `{name}` placeholders
+for values given at lookup, `{{` and `}}` for literal braces and `\n` for a
+newline.
+
+Entries can have variant forms for grammatical number and gender, selected
+with a bracketed suffix on the key. The suffix is a plural category (`zero`,
+`one`, `two`, `few`, `many`, `other`), a gender (`male`, `female`,
+`neuter`) or both separated by a comma:
+
+```text
+greeting = Hello, {name}!
+coins[one] = {n} coin
+coins[other] = {n} coins
+found[male] = He found {item}.
+found[female] = She found {item}.
+found = They found {item}.
+```
+
+The plural categories are the ones from the Unicode CLDR, and which of them
+a count falls into depends on the catalog language.
+ */
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Plural category of a count.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Plural {
+    Zero,
+    One,
+    Two,
+    Few,
+    Many,
+    Other,
+}
+
+/// Grammatical gender of the subject of a string.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Gender {
+    Male,
+    Female,
+    Neuter,
+}
+
+/// Return the plural category of a count in a language. The language is
+/// identified by its ISO 639-1 code, optionally followed by a region, eg.
+/// "en" or "pt_BR". Unknown languages get the English rules.
+pub fn plural_category(language: &str, n: u64) -> Plural {
+    use self::Plural::*;
+    let lang = language.split(|c: char| c == '-' || c == '_').next().unwrap_or("");
+    let (n10, n100) = (n % 10, n % 100);
+    match lang {
+        "ja" | "ko" | "zh" | "th" | "vi" | "id" | "ms" => Other,
+        "fr" | "pt" => if n <= 1 { One } else { Other },
+        "ru" | "uk" | "be" | "sr" | "hr" | "bs" => {
+            if n10 == 1 && n100 != 11 { One }
+            else if n10 >= 2 && n10 <= 4 && (n100 < 12 || n100 > 14) { Few }
+            else { Many }
+        }
+        "pl" => {
+            if n == 1 { One }
+            else if n10 >= 2 && n10 <= 4 && (n100 < 12 || n100 > 14) { Few }
+            else { Many }
+        }
+        "cs" | "sk" => {
+            if n == 1 { One }
+            else if n >= 2 && n <= 4 { Few }
+            else { Other }
+        }
+        "he" => {
+            if n == 1 { One }
+            else if n == 2 { Two }
+            else { Other }
+        }
+        "ar" => {
+            if n == 0 { Zero }
+            else if n == 1 { One }
+            else if n == 2 { Two }
+            else if n100 >= 3 && n100 <= 10 { Few }
+            else if n100 >= 11 { Many }
+            else { Other }
+        }
+        _ => if n == 1 { One } else { Other }
+    }
+}
+
+/// Values for the placeholders of a catalog string.
+#[derive(Clone, Debug)]
+pub struct Args {
+    values: Vec<(String, String)>,
+    count: Option<u64>,
+    gender: Option<Gender>,
+}
+
+impl Args {
+    pub fn new() -> Args {
+        Args {
+            values: Vec::new(),
+            count: None,
+            gender: None,
+        }
+    }
+
+    /// Set the value of a placeholder.
+    pub fn arg<T: Display>(mut self, name: &str, value: T) -> Args {
+        self.values.push((name.to_string(), format!("{}", value)));
+        self
+    }
+
+    /// Set the value of a numeric placeholder that also selects the plural
+    /// form of the string.
+    pub fn count(mut self, name: &str, n: u64) -> Args {
+        self.count = Some(n);
+        self.arg(name, n)
+    }
+
+    /// Set the gender that selects the gendered form of the string.
+    pub fn gender(mut self, gender: Gender) -> Args {
+        self.gender = Some(gender); self
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        // Later values override earlier ones.
+        self.values.iter().rev().find(|&&(ref k, _)| &k[..] == name).map(|&(_, ref v)| &v[..])
+    }
+}
+
+/// The localized strings of a language.
+#[derive(Clone, Debug)]
+pub struct Catalog {
+    language: String,
+    /// Texts by key, gender and plural category. The "other" plural form
+    /// is stored as None.
+    entries: HashMap<(String, Option<Gender>, Option<Plural>), String>,
+}
+
+impl Catalog {
+    /// Create an empty catalog. Looking up strings from it returns the
+    /// keys with the placeholders filled.
+    pub fn new(language: &str) -> Catalog {
+        Catalog {
+            language: language.to_string(),
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Parse a catalog file for a language.
+    pub fn parse(language: &str, text: &str) -> Result<Catalog, String> {
+        let mut ret = Catalog::new(language);
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.len() == 0 || line.starts_with("#") { continue; }
+            let eq = match line.find('=') {
+                Some(i) => i,
+                None => { return Err(format!("Line {}: Missing '='", n + 1)); }
+            };
+            let (key, gender, plural) = match parse_key(line[..eq].trim()) {
+                Ok(x) => x,
+                Err(e) => { return Err(format!("Line {}: {}", n + 1, e)); }
+            };
+            ret.entries.insert((key, gender, plural), unescape(line[eq + 1..].trim()));
+        }
+        Ok(ret)
+    }
+
+    /// Load a catalog file for a language.
+    pub fn load(language: &str, path: &Path) -> Result<Catalog, String> {
+        let mut text = String::new();
+        try!(File::open(path).and_then(|mut f| f.read_to_string(&mut text))
+             .map_err(|e| format!("{}", e)));
+        Catalog::parse(language, &text[..])
+    }
+
+    /// The language code of the catalog.
+    pub fn language(&self) -> &str { &self.language[..] }
+
+    /// Add a string to the catalog.
+    pub fn insert(&mut self, key: &str, gender: Option<Gender>, plural: Option<Plural>, text: &str) {
+        let plural = if plural == Some(Plural::Other) { None } else { plural };
+        self.entries.insert((key.to_string(), gender, plural), text.to_string());
+    }
+
+    /// Return whether the catalog has any form of a string.
+    pub fn contains(&self, key: &str) -> bool {
+        self.entries.keys().any(|&(ref k, _, _)| &k[..] == key)
+    }
+
+    /// Look up a string and fill in its placeholders. The form of the
+    /// string is chosen by the count and gender in the args, falling back
+    /// to the "other" plural form and the genderless form. If the catalog
+    /// doesn't have the string, the key itself is used as the string, so
+    /// untranslated strings stay visible.
+    pub fn tr(&self, key: &str, args: &Args) -> String {
+        let plural = args.count.map(|n| plural_category(&self.language[..], n));
+        let plural = if plural == Some(Plural::Other) { None } else { plural };
+        let mut text = key;
+        for &(g, p) in [(args.gender, plural), (args.gender, None), (None, plural), (None, None)].iter() {
+            if let Some(t) = self.entries.get(&(key.to_string(), g, p)) {
+                text = &t[..];
+                break;
+            }
+        }
+        substitute(text, args)
+    }
+}
+
+/// Parse an entry key with an optional bracketed form suffix.
+fn parse_key(key: &str) -> Result<(String, Option<Gender>, Option<Plural>), String> {
+    let open = match key.find('[') {
+        Some(i) => i,
+        None => { return Ok((key.to_string(), None, None)); }
+    };
+    if !key.ends_with("]") { return Err(format!("Bad key {}", key)); }
+    let name = key[..open].trim().to_string();
+    let mut gender = None;
+    let mut plural = None;
+    for form in key[open + 1..key.len() - 1].split(',') {
+        match form.trim() {
+            "male" => { gender = Some(Gender::Male); }
+            "female" => { gender = Some(Gender::Female); }
+            "neuter" => { gender = Some(Gender::Neuter); }
+            "zero" => { plural = Some(Plural::Zero); }
+            "one" => { plural = Some(Plural::One); }
+            "two" => { plural = Some(Plural::Two); }
+            "few" => { plural = Some(Plural::Few); }
+            "many" => { plural = Some(Plural::Many); }
+            "other" => { plural = None; }
+            x => { return Err(format!("Unknown form {}", x)); }
+        }
+    }
+    Ok((name, gender, plural))
+}
+
+fn unescape(text: &str) -> String {
+    let mut ret = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' { ret.push(c); continue; }
+        match chars.next() {
+            Some('n') => { ret.push('\n'); }
+            Some(c) => { ret.push(c); }
+            None => { ret.push('\\'); }
+        }
+    }
+    ret
+}
+
+/// Replace the placeholders in text with values from args. Unknown
+/// placeholders are left as they are.
+fn substitute(text: &str, args: &Args) -> String {
+    let mut ret = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if (c == '{' || c == '}') && chars.peek() == Some(&c) {
+            chars.next();
+            ret.push(c);
+            continue;
+        }
+        if c != '{' { ret.push(c); continue; }
+
+        let mut name = String::new();
+        let mut closed = false;
+        while let Some(c) = chars.next() {
+            if c == '}' { closed = true; break; }
+            name.push(c);
+        }
+        match args.get(&name[..]) {
+            Some(value) if closed => { ret.push_str(value); }
+            _ => {
+                ret.push('{');
+                ret.push_str(&name[..]);
+                if closed { ret.push('}'); }
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_plural_category() {
+        use super::plural_category;
+        use super::Plural::*;
+
+        assert_eq!(One, plural_category("en", 1));
+        assert_eq!(Other, plural_category("en", 0));
+        assert_eq!(One, plural_category("fr", 0));
+        assert_eq!(Other, plural_category("ja", 1));
+        assert_eq!(One, plural_category("ru", 21));
+        assert_eq!(Few, plural_category("ru_RU", 23));
+        assert_eq!(Many, plural_category("ru", 12));
+        assert_eq!(Few, plural_category("pl", 22));
+        assert_eq!(Many, plural_category("pl", 21));
+        assert_eq!(Two, plural_category("ar", 2));
+        assert_eq!(Many, plural_category("ar", 11));
+    }
+
+    #[test]
+    fn test_catalog() {
+        use super::{Catalog, Args, Gender};
+
+        let cat = Catalog::parse("en", r"
+# Comment
+greeting = Hello, {name}!
+coins[one] = {n} coin
+coins[other] = {n} coins
+found[male] = He found {item}.
+found[female, one] = She found one {item}.
+found = They found {item}.
+braces = {{{n}}}\nok
+").unwrap();
+
+        assert_eq!("en", cat.language());
+        assert_eq!("Hello, Bob!", cat.tr("greeting", &Args::new().arg("name", "Bob")));
+        assert_eq!("Hello, {name}!", cat.tr("greeting", &Args::new()));
+        assert_eq!("1 coin", cat.tr("coins", &Args::new().count("n", 1)));
+        assert_eq!("3 coins", cat.tr("coins", &Args::new().count("n", 3)));
+        assert_eq!("He found a key.",
+                   cat.tr("found", &Args::new().gender(Gender::Male).arg("item", "a key")));
+        assert_eq!("She found one key.",
+                   cat.tr("found", &Args::new().gender(Gender::Female).count("n", 1).arg("item", "key")));
+        assert_eq!("They found keys.",
+                   cat.tr("found", &Args::new().gender(Gender::Female).count("n", 2).arg("item", "keys")));
+        assert_eq!("{7}\nok", cat.tr("braces", &Args::new().count("n", 7)));
+        assert_eq!("missing 1", cat.tr("missing {x}", &Args::new().arg("x", 1)));
+        assert!(cat.contains("coins"));
+        assert!(!cat.contains("missing"));
+
+        assert!(Catalog::parse("en", "no equals sign").is_err());
+        assert!(Catalog::parse("en", "x[plenty] = y").is_err());
+    }
+}
@@ -0,0 +1,267 @@
+/*!
+Procedural map generators.
+
+The generators draw all their randomness from the Rng they are given, so a
+map can be reproduced from the seed of the Rng. Make the Rng with
+`seeded_rng`, and show the seed to players as a readable code with
+`seed.to_vorud()`.
+ */
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+use geom::{V2, Rect};
+use tilemap::{TileMap, Layer};
+use ::{Anchor};
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Terrain {
+    Wall,
+    Floor,
+    Corridor,
+}
+
+impl Terrain {
+    pub fn is_open(self) -> bool { self != Terrain::Wall }
+}
+
+static DIRS: [V2<i32>; 4] = [V2(0, -1), V2(1, 0), V2(0, 1), V2(-1, 0)];
+
+/// Create a map generation Rng from a seed number.
+pub fn seeded_rng(seed: u32) -> XorShiftRng {
+    // Mix in constants, XorShift can't be seeded with all zeroes.
+    SeedableRng::from_seed([seed, 0x9e3779b9, 0x7f4a7c15, 0x85ebca6b])
+}
+
+/// Split an area recursively into rectangles that are between min_size and
+/// twice min_size along both axes. Areas smaller than min_size are
+/// returned as they are.
+pub fn bsp<R: Rng>(rng: &mut R, area: Rect<i32>, min_size: i32) -> Vec<Rect<i32>> {
+    assert!(min_size > 0);
+    let Rect(pos, dim) = area;
+    let (split_x, split_y) = (dim.0 >= 2 * min_size, dim.1 >= 2 * min_size);
+    let vertical_cut = match (split_x, split_y) {
+        (false, false) => return vec![area],
+        (true, false) => true,
+        (false, true) => false,
+        // Cut across the longer axis to keep the parts squarish.
+        (true, true) => dim.0 >= dim.1,
+    };
+
+    let (a, b) = if vertical_cut {
+        let w = rng.gen_range(min_size, dim.0 - min_size + 1);
+        (Rect(pos, V2(w, dim.1)), Rect(pos + V2(w, 0), V2(dim.0 - w, dim.1)))
+    } else {
+        let h = rng.gen_range(min_size, dim.1 - min_size + 1);
+        (Rect(pos, V2(dim.0, h)), Rect(pos + V2(0, h), V2(dim.0, dim.1 - h)))
+    };
+    let mut ret = bsp(rng, a, min_size);
+    ret.push_all(&bsp(rng, b, min_size));
+    ret
+}
+
+/// Generate rooms connected by corridors. The map is split into BSP
+/// cells with one room of at least min_room cells across in each, and each
+/// room is joined to the next one with an L-shaped corridor.
+pub fn rooms_and_corridors<R: Rng>(rng: &mut R, size: V2<i32>, min_room: i32) -> TileMap<Terrain> {
+    assert!(size.0 >= min_room + 4 && size.1 >= min_room + 4, "Map too small for rooms");
+    let mut map = TileMap::new(size, Terrain::Wall);
+
+    // Leave walls around the map edge and between the rooms.
+    let mut rooms = Vec::new();
+    for cell in bsp(rng, Rect(V2(1, 1), size - V2(2, 2)), min_room + 1).into_iter() {
+        let Rect(pos, dim) = cell;
+        let room_dim = V2(rng.gen_range(min_room, dim.0), rng.gen_range(min_room, dim.1));
+        let room_pos = pos + V2(rng.gen_range(0, dim.0 - room_dim.0), rng.gen_range(0, dim.1 - room_dim.1));
+        let room = Rect(room_pos, room_dim);
+        for p in room.iter() { map.layer_mut(0)[p] = Terrain::Floor; }
+        rooms.push(room);
+    }
+
+    for i in 1..rooms.len() {
+        let (a, b) = (rooms[i - 1].point(Anchor::Center), rooms[i].point(Anchor::Center));
+        let corner = if rng.gen() { V2(b.0, a.1) } else { V2(a.0, b.1) };
+        for p in segment(a, corner).into_iter().chain(segment(corner, b).into_iter()) {
+            let layer = map.layer_mut(0);
+            if layer[p] == Terrain::Wall { layer[p] = Terrain::Corridor; }
+        }
+    }
+    map
+}
+
+/// Cells on a horizontal or vertical line segment, including both ends.
+fn segment(a: V2<i32>, b: V2<i32>) -> Vec<V2<i32>> {
+    let step = V2((b.0 - a.0).signum(), (b.1 - a.1).signum());
+    let mut ret = vec![a];
+    let mut p = a;
+    while p != b {
+        p = p + step;
+        ret.push(p);
+    }
+    ret
+}
+
+/// Generate a cave with a cellular automaton. The map starts as random
+/// noise with wall_chance of each cell being wall, and is smoothed by
+/// making every cell with at least five walls in its 3x3 neighborhood a
+/// wall. Only the largest connected open area is kept.
+pub fn cave<R: Rng>(rng: &mut R, size: V2<i32>, wall_chance: f32, iterations: usize) -> TileMap<Terrain> {
+    let inner = Rect(V2(1, 1), size - V2(2, 2));
+    let mut map = TileMap::new(size, Terrain::Wall);
+    for p in inner.iter() {
+        if rng.gen::<f32>() >= wall_chance { map.layer_mut(0)[p] = Terrain::Floor; }
+    }
+
+    for _ in 0..iterations {
+        let prev = map.layer(0).clone();
+        for p in inner.iter() {
+            let walls = Rect(p - V2(1, 1), V2(3, 3)).iter()
+                .filter(|&q| prev[q] == Terrain::Wall)
+                .count();
+            map.layer_mut(0)[p] = if walls >= 5 { Terrain::Wall } else { Terrain::Floor };
+        }
+    }
+
+    keep_largest_region(map.layer_mut(0));
+    map
+}
+
+/// Generate a cave by carving open cells along a random walk from the
+/// center of the map until floor_fraction of the map is open.
+pub fn drunkard_walk<R: Rng>(rng: &mut R, size: V2<i32>, floor_fraction: f32) -> TileMap<Terrain> {
+    assert!(floor_fraction > 0.0 && floor_fraction <= 1.0);
+    let inner = Rect(V2(1, 1), size - V2(2, 2));
+    let target = (inner.area() as f32 * floor_fraction) as usize;
+    let mut map = TileMap::new(size, Terrain::Wall);
+
+    let mut pos = inner.point(Anchor::Center);
+    let mut count = 0;
+    loop {
+        if map.layer(0)[pos] == Terrain::Wall {
+            map.layer_mut(0)[pos] = Terrain::Floor;
+            count += 1;
+            if count >= target { break; }
+        }
+        let next = pos + DIRS[rng.gen_range(0, 4)];
+        if inner.contains(&next) { pos = next; }
+    }
+    map
+}
+
+/// Return the orthogonally connected regions of open cells. Every cell is
+/// visited once, however many regions there are.
+pub fn regions(layer: &Layer<Terrain>) -> Vec<Vec<V2<i32>>> {
+    let bounds = layer.bounds();
+    let mut seen = Layer::new(layer.size(), false);
+    let mut ret = Vec::new();
+    for (p, t) in layer.iter() {
+        if !t.is_open() || seen[p] { continue; }
+        // Flood fill the region, the region list doubles as the queue.
+        seen[p] = true;
+        let mut region = vec![p];
+        let mut k = 0;
+        while k < region.len() {
+            let q = region[k];
+            for &d in DIRS.iter() {
+                let r = q + d;
+                if bounds.contains(&r) && !seen[r] && layer[r].is_open() {
+                    seen[r] = true;
+                    region.push(r);
+                }
+            }
+            k += 1;
+        }
+        ret.push(region);
+    }
+    ret
+}
+
+/// Return whether all the open cells of the layer are connected.
+pub fn is_connected(layer: &Layer<Terrain>) -> bool {
+    regions(layer).len() <= 1
+}
+
+/// Fill in all the open regions except the largest one.
+pub fn keep_largest_region(layer: &mut Layer<Terrain>) {
+    let mut regions = regions(layer);
+    regions.sort_by(|a, b| b.len().cmp(&a.len()));
+    for region in regions.iter().skip(1) {
+        for &p in region.iter() { layer[p] = Terrain::Wall; }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_bsp() {
+        use super::{bsp, seeded_rng};
+        use geom::{V2, Rect};
+
+        let area = Rect(V2(3, 2), V2(60, 40));
+        let cells = bsp(&mut seeded_rng(1), area, 6);
+        assert!(cells.len() > 1);
+        assert_eq!(area.area(), cells.iter().map(|c| c.area()).fold(0, |a, b| a + b));
+        for (i, a) in cells.iter().enumerate() {
+            assert!((a.1).0 >= 6 && (a.1).0 < 12 && (a.1).1 >= 6 && (a.1).1 < 12);
+            assert!(cells[i + 1..].iter().all(|b| !a.intersects(b)));
+        }
+    }
+
+    #[test]
+    fn test_regions() {
+        use super::{regions, keep_largest_region, is_connected, Terrain};
+        use geom::V2;
+        use tilemap::Layer;
+        use text::Map2DUtil;
+
+        let map = "\
+..#.
+#.#.
+..##
+#..#";
+        let mut layer = Layer::new(V2(4, 4), Terrain::Wall);
+        for (c, x, y) in map.chars().map2d() {
+            if c == '.' { layer[V2(x, y)] = Terrain::Floor; }
+        }
+        let mut sizes: Vec<usize> = regions(&layer).iter().map(|r| r.len()).collect();
+        sizes.sort();
+        assert_eq!(vec![2, 7], sizes);
+        assert!(!is_connected(&layer));
+
+        keep_largest_region(&mut layer);
+        assert!(is_connected(&layer));
+        assert_eq!(Terrain::Wall, layer[V2(3, 1)]);
+        assert_eq!(Terrain::Floor, layer[V2(2, 3)]);
+    }
+
+    #[test]
+    fn test_generators() {
+        use super::{seeded_rng, rooms_and_corridors, cave, drunkard_walk, is_connected, Terrain};
+        use geom::V2;
+        use vorud::{ToVorud, FromVorud};
+
+        let size = V2(60, 30);
+        let seed: u32 = FromVorud::from_vorud(&0xdeadbeefu32.to_vorud()).unwrap();
+        assert_eq!(0xdeadbeef, seed);
+
+        let maps = vec![
+            rooms_and_corridors(&mut seeded_rng(seed), size, 4),
+            cave(&mut seeded_rng(seed), size, 0.45, 4),
+            drunkard_walk(&mut seeded_rng(seed), size, 0.4)];
+        for map in maps.iter() {
+            assert_eq!(size, map.size());
+            assert!(is_connected(map.layer(0)));
+            // Walled in.
+            for (p, &t) in map.layer(0).iter() {
+                if p.0 == 0 || p.1 == 0 || p.0 == size.0 - 1 || p.1 == size.1 - 1 {
+                    assert_eq!(Terrain::Wall, t);
+                }
+            }
+            assert!(map.layer(0).iter().filter(|&(_, t)| t.is_open()).count() > 100);
+        }
+
+        // Same seed, same map.
+        assert_eq!(maps[0], rooms_and_corridors(&mut seeded_rng(seed), size, 4));
+        assert_eq!(maps[1], cave(&mut seeded_rng(seed), size, 0.45, 4));
+        assert_eq!(maps[2], drunkard_walk(&mut seeded_rng(seed), size, 0.4));
+        assert!(maps[0] != rooms_and_corridors(&mut seeded_rng(seed + 1), size, 4));
+    }
+}
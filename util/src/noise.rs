@@ -0,0 +1,345 @@
+/*!
+Coherent noise.
+
+The noise functions change smoothly with the input position, unlike the
+plain integer hash `calx_util::noise`, so they can be used for things like
+terrain heights and textures. All the functions return values in -1..1 and
+are deterministic for a given seed.
+ */
+
+use std::num::Float;
+use std::num::wrapping::Wrapping;
+use std::ops::Mul;
+use geom::{V2, V3};
+
+static GRAD2: [V2<f32>; 8] = [
+    V2(1.0, 1.0), V2(-1.0, 1.0), V2(1.0, -1.0), V2(-1.0, -1.0),
+    V2(1.0, 0.0), V2(-1.0, 0.0), V2(0.0, 1.0), V2(0.0, -1.0)];
+
+static GRAD3: [V3<f32>; 12] = [
+    V3(1.0, 1.0, 0.0), V3(-1.0, 1.0, 0.0), V3(1.0, -1.0, 0.0), V3(-1.0, -1.0, 0.0),
+    V3(1.0, 0.0, 1.0), V3(-1.0, 0.0, 1.0), V3(1.0, 0.0, -1.0), V3(-1.0, 0.0, -1.0),
+    V3(0.0, 1.0, 1.0), V3(0.0, -1.0, 1.0), V3(0.0, 1.0, -1.0), V3(0.0, -1.0, -1.0)];
+
+/// A seeded noise source. Different seeds give unrelated noise fields.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Noise {
+    pub seed: u32,
+}
+
+impl Noise {
+    pub fn new(seed: u32) -> Noise { Noise { seed: seed } }
+
+    /// Random value for an integer lattice point.
+    fn lattice(&self, x: i32, y: i32, z: i32) -> f32 {
+        let n = Wrapping(self.seed as i32) * Wrapping(1013) + Wrapping(x) * Wrapping(1619)
+            + Wrapping(y) * Wrapping(31337) + Wrapping(z) * Wrapping(6971);
+        let Wrapping(n) = n;
+        ::noise(n)
+    }
+
+    /// Index into a gradient table for an integer lattice point.
+    fn gradient(&self, x: i32, y: i32, z: i32, n: usize) -> usize {
+        ((self.lattice(x, y, z) + 1.0) * 0.5 * n as f32) as usize % n
+    }
+
+    /// Value noise, interpolated random values at the integer lattice
+    /// points. Cheap but blocky looking.
+    pub fn value2(&self, p: V2<f32>) -> f32 {
+        let (x0, y0) = (p.0.floor(), p.1.floor());
+        let (i, j) = (x0 as i32, y0 as i32);
+        let (u, v) = (fade(p.0 - x0), fade(p.1 - y0));
+        lerp(v,
+             lerp(u, self.lattice(i, j, 0), self.lattice(i + 1, j, 0)),
+             lerp(u, self.lattice(i, j + 1, 0), self.lattice(i + 1, j + 1, 0)))
+    }
+
+    /// Value noise in three dimensions.
+    pub fn value3(&self, p: V3<f32>) -> f32 {
+        let (x0, y0, z0) = (p.0.floor(), p.1.floor(), p.2.floor());
+        let (i, j, k) = (x0 as i32, y0 as i32, z0 as i32);
+        let (u, v, w) = (fade(p.0 - x0), fade(p.1 - y0), fade(p.2 - z0));
+        let layer = |k| lerp(v,
+            lerp(u, self.lattice(i, j, k), self.lattice(i + 1, j, k)),
+            lerp(u, self.lattice(i, j + 1, k), self.lattice(i + 1, j + 1, k)));
+        lerp(w, layer(k), layer(k + 1))
+    }
+
+    /// Gradient (Perlin) noise. Zero at the integer lattice points, with
+    /// less grid-aligned features than value noise.
+    pub fn perlin2(&self, p: V2<f32>) -> f32 {
+        let (x0, y0) = (p.0.floor(), p.1.floor());
+        let (i, j) = (x0 as i32, y0 as i32);
+        let (fx, fy) = (p.0 - x0, p.1 - y0);
+        let g = |di: i32, dj: i32| {
+            GRAD2[self.gradient(i + di, j + dj, 0, 8)].dot(V2(fx - di as f32, fy - dj as f32))
+        };
+        let (u, v) = (fade(fx), fade(fy));
+        lerp(v, lerp(u, g(0, 0), g(1, 0)), lerp(u, g(0, 1), g(1, 1)))
+    }
+
+    /// Gradient (Perlin) noise in three dimensions.
+    pub fn perlin3(&self, p: V3<f32>) -> f32 {
+        let (x0, y0, z0) = (p.0.floor(), p.1.floor(), p.2.floor());
+        let (i, j, k) = (x0 as i32, y0 as i32, z0 as i32);
+        let (fx, fy, fz) = (p.0 - x0, p.1 - y0, p.2 - z0);
+        let g = |di: i32, dj: i32, dk: i32| {
+            GRAD3[self.gradient(i + di, j + dj, k + dk, 12)]
+                .dot(V3(fx - di as f32, fy - dj as f32, fz - dk as f32))
+        };
+        let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+        let layer = |dk| lerp(v, lerp(u, g(0, 0, dk), g(1, 0, dk)), lerp(u, g(0, 1, dk), g(1, 1, dk)));
+        clamp(lerp(w, layer(0), layer(1)))
+    }
+
+    /// Simplex noise. Similar to Perlin noise, but sums contributions from
+    /// the corners of a triangle grid, which is faster in higher
+    /// dimensions and has no visible square grid.
+    pub fn simplex2(&self, p: V2<f32>) -> f32 {
+        let f2 = 0.5 * (3.0f32.sqrt() - 1.0);
+        let g2 = (3.0 - 3.0f32.sqrt()) / 6.0;
+
+        // Skew the input into the square grid of the triangle pairs.
+        let s = (p.0 + p.1) * f2;
+        let (i, j) = ((p.0 + s).floor(), (p.1 + s).floor());
+        let t = (i + j) * g2;
+        let d0 = V2(p.0 - (i - t), p.1 - (j - t));
+        // Which triangle of the pair the point is in.
+        let (di, dj) = if d0.0 > d0.1 { (1, 0) } else { (0, 1) };
+        let d1 = d0 - V2(di as f32, dj as f32) + V2(g2, g2);
+        let d2 = d0 - V2(1.0, 1.0) + V2(2.0 * g2, 2.0 * g2);
+
+        let (i, j) = (i as i32, j as i32);
+        let corner = |d: V2<f32>, ci: i32, cj: i32| {
+            let t = 0.5 - d.dot(d);
+            if t < 0.0 { 0.0 } else {
+                let t = t * t;
+                t * t * GRAD2[self.gradient(i + ci, j + cj, 0, 8)].dot(d)
+            }
+        };
+        clamp(70.0 * (corner(d0, 0, 0) + corner(d1, di, dj) + corner(d2, 1, 1)))
+    }
+
+    /// Simplex noise in three dimensions.
+    pub fn simplex3(&self, p: V3<f32>) -> f32 {
+        let (f3, g3) = (1.0 / 3.0, 1.0 / 6.0);
+
+        let s = (p.0 + p.1 + p.2) * f3;
+        let (i, j, k) = ((p.0 + s).floor(), (p.1 + s).floor(), (p.2 + s).floor());
+        let t = (i + j + k) * g3;
+        let d0 = V3(p.0 - (i - t), p.1 - (j - t), p.2 - (k - t));
+
+        // The cube is split into six tetrahedra, find the one the point is
+        // in from the order of the coordinates.
+        let (a, b) = if d0.0 >= d0.1 {
+            if d0.1 >= d0.2 { ((1, 0, 0), (1, 1, 0)) }
+            else if d0.0 >= d0.2 { ((1, 0, 0), (1, 0, 1)) }
+            else { ((0, 0, 1), (1, 0, 1)) }
+        } else {
+            if d0.1 < d0.2 { ((0, 0, 1), (0, 1, 1)) }
+            else if d0.0 < d0.2 { ((0, 1, 0), (0, 1, 1)) }
+            else { ((0, 1, 0), (1, 1, 0)) }
+        };
+        let offset = |c: (i32, i32, i32), n: f32| {
+            d0 - V3(c.0 as f32, c.1 as f32, c.2 as f32) + V3(n * g3, n * g3, n * g3)
+        };
+
+        let (i, j, k) = (i as i32, j as i32, k as i32);
+        let corner = |c: (i32, i32, i32), n: f32| {
+            let d = offset(c, n);
+            let t = 0.5 - d.dot(d);
+            if t < 0.0 { 0.0 } else {
+                let t = t * t;
+                t * t * GRAD3[self.gradient(i + c.0, j + c.1, k + c.2, 12)].dot(d)
+            }
+        };
+        clamp(76.0 * (corner((0, 0, 0), 0.0) + corner(a, 1.0) + corner(b, 2.0) + corner((1, 1, 1), 3.0)))
+    }
+}
+
+/// Quintic smoothstep, has zero first and second derivatives at 0 and 1 so
+/// the lattice cells blend together without creases.
+fn fade(t: f32) -> f32 { t * t * t * (t * (t * 6.0 - 15.0) + 10.0) }
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 { a + t * (b - a) }
+
+fn clamp(x: f32) -> f32 { ::clamp(-1.0, 1.0, x) }
+
+/// Parameters for summing octaves of noise at increasing frequencies.
+#[derive(Copy, Clone, Debug)]
+pub struct Fractal {
+    octaves: usize,
+    lacunarity: f32,
+    gain: f32,
+}
+
+impl Fractal {
+    /// Create a fractal sum with 4 octaves that double the frequency and
+    /// halve the amplitude each step.
+    pub fn new() -> Fractal {
+        Fractal {
+            octaves: 4,
+            lacunarity: 2.0,
+            gain: 0.5,
+        }
+    }
+
+    /// Set the number of noise layers.
+    pub fn octaves(mut self, octaves: usize) -> Fractal {
+        assert!(octaves > 0);
+        self.octaves = octaves;
+        self
+    }
+
+    /// Set the frequency multiplier between octaves.
+    pub fn lacunarity(mut self, lacunarity: f32) -> Fractal {
+        self.lacunarity = lacunarity;
+        self
+    }
+
+    /// Set the amplitude multiplier between octaves.
+    pub fn gain(mut self, gain: f32) -> Fractal {
+        self.gain = gain;
+        self
+    }
+
+    /// Sum the octaves transformed with g, normalized to the range of g.
+    fn sum<P, F, G>(&self, f: F, p: P, g: G) -> f32
+        where P: Copy+Mul<f32, Output=P>, F: Fn(P) -> f32, G: Fn(f32) -> f32 {
+        let (mut freq, mut amp) = (1.0, 1.0);
+        let (mut total, mut norm) = (0.0, 0.0);
+        for _ in 0..self.octaves {
+            total += amp * g(f(p * freq));
+            norm += amp;
+            freq *= self.lacunarity;
+            amp *= self.gain;
+        }
+        total / norm
+    }
+
+    /// Fractal Brownian motion, the plain sum of the octaves. Gives
+    /// rolling hills and cloudy textures. Returns values in -1..1.
+    pub fn fbm<P, F>(&self, f: F, p: P) -> f32
+        where P: Copy+Mul<f32, Output=P>, F: Fn(P) -> f32 {
+        self.sum(f, p, |x| x)
+    }
+
+    /// Sum of the absolute values of the octaves. Gives billowy shapes
+    /// with sharp creases at the bottom. Returns values in 0..1.
+    pub fn turbulence<P, F>(&self, f: F, p: P) -> f32
+        where P: Copy+Mul<f32, Output=P>, F: Fn(P) -> f32 {
+        self.sum(f, p, |x| x.abs())
+    }
+
+    /// Sum of inverted absolute values of the octaves. Gives sharp ridges
+    /// like mountain ranges. Returns values in 0..1.
+    pub fn ridged<P, F>(&self, f: F, p: P) -> f32
+        where P: Copy+Mul<f32, Output=P>, F: Fn(P) -> f32 {
+        self.sum(f, p, |x| { let r = 1.0 - x.abs(); r * r })
+    }
+}
+
+/// Domain warping. Sample f at a position displaced by up to amount along
+/// each axis by the warp noise, which gives swirly, organic looking
+/// shapes.
+pub fn warp2<F, W>(f: F, warp: W, amount: f32, p: V2<f32>) -> f32
+    where F: Fn(V2<f32>) -> f32, W: Fn(V2<f32>) -> f32 {
+    // Sample the warp at far apart positions to get unrelated offsets for
+    // the axes.
+    let d = V2(warp(p), warp(p + V2(5.2, 1.3)));
+    f(p + d * amount)
+}
+
+/// Domain warping in three dimensions.
+pub fn warp3<F, W>(f: F, warp: W, amount: f32, p: V3<f32>) -> f32
+    where F: Fn(V3<f32>) -> f32, W: Fn(V3<f32>) -> f32 {
+    let d = V3(warp(p), warp(p + V3(5.2, 1.3, 7.7)), warp(p + V3(1.7, 9.2, 3.4)));
+    f(p + d * amount)
+}
+
+#[cfg(test)]
+mod test {
+    use geom::{V2, V3};
+    use super::Noise;
+
+    fn grid2() -> Vec<V2<f32>> {
+        (0..40).flat_map(|y| (0..40).map(move |x| V2(x as f32 * 0.173 - 3.0, y as f32 * 0.191 - 3.0)))
+            .collect()
+    }
+
+    fn grid3() -> Vec<V3<f32>> {
+        (0..12).flat_map(|z| grid2().into_iter().map(move |p| V3(p.0, p.1, z as f32 * 0.37 - 2.0)))
+            .collect()
+    }
+
+    #[test]
+    fn test_range_and_continuity() {
+        let n = Noise::new(1);
+        let e = 1e-4;
+        let fs2: Vec<Box<Fn(V2<f32>) -> f32>> = vec![
+            Box::new(|p| n.value2(p)), Box::new(|p| n.perlin2(p)), Box::new(|p| n.simplex2(p))];
+        for f in fs2.iter() {
+            let (mut mn, mut mx) = (0.0f32, 0.0f32);
+            for &p in grid2().iter() {
+                let x = f(p);
+                assert!(x >= -1.0 && x <= 1.0);
+                // Small steps give small changes. Tight enough to catch a
+                // seam where a simplex corner drops out too early.
+                assert!((f(p + V2(e, e)) - x).abs() < 0.002);
+                if x < mn { mn = x; }
+                if x > mx { mx = x; }
+            }
+            // Covers a decent part of the range.
+            assert!(mn < -0.3 && mx > 0.3);
+        }
+
+        let fs3: Vec<Box<Fn(V3<f32>) -> f32>> = vec![
+            Box::new(|p| n.value3(p)), Box::new(|p| n.perlin3(p)), Box::new(|p| n.simplex3(p))];
+        for f in fs3.iter() {
+            let (mut mn, mut mx) = (0.0f32, 0.0f32);
+            for &p in grid3().iter() {
+                let x = f(p);
+                assert!(x >= -1.0 && x <= 1.0);
+                assert!((f(p + V3(e, e, e)) - x).abs() < 0.002);
+                if x < mn { mn = x; }
+                if x > mx { mx = x; }
+            }
+            assert!(mn < -0.3 && mx > 0.3);
+        }
+
+        // Gradient noise vanishes at the lattice points.
+        assert_eq!(0.0, n.perlin2(V2(3.0, -2.0)));
+    }
+
+    #[test]
+    fn test_seeds() {
+        let p = V2(1.37, -4.21);
+        assert_eq!(Noise::new(7).simplex2(p), Noise::new(7).simplex2(p));
+        assert!(Noise::new(7).simplex2(p) != Noise::new(8).simplex2(p));
+        assert!(Noise::new(7).value2(p) != Noise::new(8).value2(p));
+        assert!(Noise::new(7).perlin3(V3(p.0, p.1, 0.5)) != Noise::new(8).perlin3(V3(p.0, p.1, 0.5)));
+    }
+
+    #[test]
+    fn test_fractal() {
+        use super::{Fractal, warp2};
+
+        let n = Noise::new(3);
+        let fractal = Fractal::new().octaves(5);
+        for &p in grid2().iter() {
+            let x = fractal.fbm(|p| n.simplex2(p), p);
+            assert!(x >= -1.0 && x <= 1.0);
+            let x = fractal.turbulence(|p| n.perlin2(p), p);
+            assert!(x >= 0.0 && x <= 1.0);
+            let x = fractal.ridged(|p| n.perlin2(p), p);
+            assert!(x >= 0.0 && x <= 1.0);
+        }
+        // One octave is just the noise.
+        let p = V3(0.3, 2.2, -1.5);
+        assert_eq!(n.value3(p), Fractal::new().octaves(1).fbm(|p| n.value3(p), p));
+
+        let p = V2(0.3, 2.2);
+        assert_eq!(n.value2(p), warp2(|p| n.value2(p), |p| n.simplex2(p), 0.0, p));
+        assert!(n.value2(p) != warp2(|p| n.value2(p), |p| n.simplex2(p), 2.0, p));
+    }
+}
@@ -0,0 +1,240 @@
+/*!
+Poisson disc sampling.
+
+Scatters random points so that no two are closer than a minimum distance,
+but otherwise fill the area evenly. This looks much more natural than
+uniformly random points, which clump together and leave big gaps. Uses
+Bridson's algorithm, where new points are tried around the already placed
+ones until there is no room left.
+ */
+
+use std::num::Float;
+use std::f32::consts::PI;
+use std::collections::HashSet;
+use rand::Rng;
+use geom::{V2, Rect};
+
+/// Sample points in an area at least min_dist apart.
+pub fn poisson_disc<R: Rng>(rng: &mut R, area: Rect<f32>, min_dist: f32) -> Vec<V2<f32>> {
+    let seed = random_point(rng, area);
+    sample(rng, area, min_dist, min_dist, vec![seed], |_| Some(min_dist))
+}
+
+/// Sample points with a distance between min_dist and max_dist that
+/// varies over the area. The density function gives values in -1..1, so a
+/// noise function can be used directly. Points are min_dist apart where
+/// density is 1 and max_dist apart where it's -1.
+pub fn poisson_disc_variable<R, F>(rng: &mut R, area: Rect<f32>, min_dist: f32, max_dist: f32,
+                                   density: F) -> Vec<V2<f32>>
+    where R: Rng, F: Fn(V2<f32>) -> f32 {
+    assert!(max_dist >= min_dist);
+    let seed = random_point(rng, area);
+    sample(rng, area, min_dist, max_dist, vec![seed], |p| {
+        let t = (::clamp(-1.0, 1.0, density(p)) + 1.0) / 2.0;
+        Some(max_dist + (min_dist - max_dist) * t)
+    })
+}
+
+/// Sample points at least min_dist apart on the open cells of a grid.
+/// Cell (x, y) covers the points from (x, y) up to (x + 1, y + 1). Every
+/// open cell is used as a starting point, so separate open regions all get
+/// filled.
+pub fn poisson_disc_mask<R, F>(rng: &mut R, cells: Rect<i32>, min_dist: f32, is_open: F) -> Vec<V2<f32>>
+    where R: Rng, F: Fn(V2<i32>) -> bool {
+    let area = Rect(V2((cells.0).0 as f32, (cells.0).1 as f32), V2((cells.1).0 as f32, (cells.1).1 as f32));
+    let seeds = cells.iter()
+        .filter(|&p| is_open(p))
+        .map(|p| V2(p.0 as f32 + rng.gen::<f32>(), p.1 as f32 + rng.gen::<f32>()))
+        .collect();
+    sample(rng, area, min_dist, min_dist, seeds, |p| {
+        if is_open(V2(p.0.floor() as i32, p.1.floor() as i32)) { Some(min_dist) } else { None }
+    })
+}
+
+/// Return the distinct grid cells the points fall in, in the order of the
+/// first point in each cell.
+pub fn snap_to_grid(points: &[V2<f32>]) -> Vec<V2<i32>> {
+    let mut seen = HashSet::new();
+    let mut ret = Vec::new();
+    for p in points.iter() {
+        let cell = V2(p.0.floor() as i32, p.1.floor() as i32);
+        if seen.insert(cell) { ret.push(cell); }
+    }
+    ret
+}
+
+fn random_point<R: Rng>(rng: &mut R, area: Rect<f32>) -> V2<f32> {
+    area.0 + V2(rng.gen::<f32>() * (area.1).0, rng.gen::<f32>() * (area.1).1)
+}
+
+/// Number of candidates tried around a point before giving up on it.
+const ATTEMPTS: usize = 30;
+
+/// Run Bridson's algorithm. The radius function gives the minimum
+/// distance to other points for a new point, or None if the position can't
+/// have a point. It must stay between min_dist and max_dist. The seeds are
+/// tried as starting points whenever the previous region is full.
+fn sample<R, F>(rng: &mut R, area: Rect<f32>, min_dist: f32, max_dist: f32,
+                seeds: Vec<V2<f32>>, radius: F) -> Vec<V2<f32>>
+    where R: Rng, F: Fn(V2<f32>) -> Option<f32> {
+    let mut grid = Grid::new(area, min_dist, max_dist);
+    let mut active = Vec::new();
+
+    for seed in seeds.into_iter() {
+        if let Some(r) = radius(seed) {
+            if grid.is_free(seed, r) { active.push(grid.insert(seed)); }
+        }
+
+        while !active.is_empty() {
+            // Try new points around a random active point, and retire the
+            // point if there's no room around it.
+            let k = rng.gen_range(0, active.len());
+            let origin = grid.points[active[k]];
+            let r = radius(origin).unwrap();
+            let mut found = false;
+            for _ in 0..ATTEMPTS {
+                let angle = rng.gen::<f32>() * 2.0 * PI;
+                let dist = r * (1.0 + rng.gen::<f32>());
+                let p = origin + V2(angle.cos() * dist, angle.sin() * dist);
+                if !area.contains(&p) { continue; }
+                if let Some(r) = radius(p) {
+                    if grid.is_free(p, r) {
+                        active.push(grid.insert(p));
+                        found = true;
+                        break;
+                    }
+                }
+            }
+            if !found { active.swap_remove(k); }
+        }
+    }
+    grid.points
+}
+
+/// Background grid for finding nearby points quickly.
+struct Grid {
+    area: Rect<f32>,
+    cell_size: f32,
+    size: V2<i32>,
+    /// How many cells away the furthest conflicting point can be.
+    reach: i32,
+    cells: Vec<Option<usize>>,
+    points: Vec<V2<f32>>,
+}
+
+impl Grid {
+    fn new(area: Rect<f32>, min_dist: f32, max_dist: f32) -> Grid {
+        assert!(min_dist > 0.0 && max_dist >= min_dist);
+        // Cells small enough that each can hold at most one point.
+        let cell_size = min_dist / 2.0f32.sqrt();
+        let size = V2(((area.1).0 / cell_size).ceil().max(1.0) as i32,
+                      ((area.1).1 / cell_size).ceil().max(1.0) as i32);
+        Grid {
+            area: area,
+            cell_size: cell_size,
+            size: size,
+            reach: (max_dist / cell_size).ceil() as i32,
+            cells: (0..(size.0 * size.1)).map(|_| None).collect(),
+            points: Vec::new(),
+        }
+    }
+
+    fn cell(&self, p: V2<f32>) -> V2<i32> {
+        let x = ((p.0 - (self.area.0).0) / self.cell_size) as i32;
+        let y = ((p.1 - (self.area.0).1) / self.cell_size) as i32;
+        V2(::clamp(0, self.size.0 - 1, x), ::clamp(0, self.size.1 - 1, y))
+    }
+
+    /// Whether there are no points closer than r to p.
+    fn is_free(&self, p: V2<f32>, r: f32) -> bool {
+        let c = self.cell(p);
+        let bounds = Rect(V2(0, 0), self.size);
+        for q in Rect(c - V2(self.reach, self.reach), V2(2 * self.reach + 1, 2 * self.reach + 1)).iter() {
+            if !bounds.contains(&q) { continue; }
+            if let Some(i) = self.cells[(q.0 + q.1 * self.size.0) as usize] {
+                let d = self.points[i] - p;
+                if d.dot(d) < r * r { return false; }
+            }
+        }
+        true
+    }
+
+    fn insert(&mut self, p: V2<f32>) -> usize {
+        let c = self.cell(p);
+        let i = self.points.len();
+        self.cells[(c.0 + c.1 * self.size.0) as usize] = Some(i);
+        self.points.push(p);
+        i
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use geom::{V2, Rect};
+    use mapgen::seeded_rng;
+
+    fn check_spacing(points: &[V2<f32>], min_dist: f32) {
+        for (i, &a) in points.iter().enumerate() {
+            for &b in points[i + 1..].iter() {
+                let d = a - b;
+                assert!(d.dot(d) >= min_dist * min_dist);
+            }
+        }
+    }
+
+    #[test]
+    fn test_poisson_disc() {
+        use super::poisson_disc;
+
+        let area = Rect(V2(-5.0, 10.0), V2(30.0, 20.0));
+        let points = poisson_disc(&mut seeded_rng(1), area, 2.0);
+        assert!(points.iter().all(|p| area.contains(p)));
+        check_spacing(&points, 2.0);
+        // No big empty holes left.
+        for p in Rect(V2(0, 0), V2(30, 20)).iter() {
+            let p = area.0 + V2(p.0 as f32 + 0.5, p.1 as f32 + 0.5);
+            assert!(points.iter().any(|&q| { let d = q - p; d.dot(d) < 4.0 * 4.0 }));
+        }
+        assert_eq!(points, poisson_disc(&mut seeded_rng(1), area, 2.0));
+        assert!(points != poisson_disc(&mut seeded_rng(2), area, 2.0));
+    }
+
+    #[test]
+    fn test_variable_density() {
+        use super::poisson_disc_variable;
+
+        let area = Rect(V2(0.0, 0.0), V2(40.0, 20.0));
+        let points = poisson_disc_variable(&mut seeded_rng(1), area, 1.0, 3.0,
+                                           |p| if p.0 < 20.0 { 1.0 } else { -1.0 });
+        check_spacing(&points, 1.0);
+        let dense = points.iter().filter(|p| p.0 < 20.0).count();
+        assert!(dense > 3 * (points.len() - dense));
+    }
+
+    #[test]
+    fn test_mask() {
+        use super::{poisson_disc_mask, snap_to_grid};
+        use text::Map2DUtil;
+
+        // Two separate rooms.
+        let map = "\
+##########
+#...######
+#...####.#
+#...####.#
+##########";
+        let open: Vec<V2<i32>> = map.chars().map2d()
+            .filter(|&(c, _, _)| c == '.').map(|(_, x, y)| V2(x, y)).collect();
+        let is_open = |p: V2<i32>| open.contains(&p);
+        let points = poisson_disc_mask(&mut seeded_rng(1), Rect(V2(0, 0), V2(10, 5)), 1.0, &is_open);
+        check_spacing(&points, 1.0);
+
+        let cells = snap_to_grid(&points);
+        assert!(cells.iter().all(|&p| is_open(p)));
+        assert!(cells.contains(&V2(8, 2)) || cells.contains(&V2(8, 3)));
+        assert!(cells.iter().any(|p| p.0 < 4));
+        for (i, a) in cells.iter().enumerate() {
+            assert!(!cells[i + 1..].contains(a));
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use std::num::{from_str_radix};
+use std::str::FromStr;
 use std::ascii::{OwnedAsciiExt};
 use color;
 use ::Color;
@@ -50,6 +51,16 @@ impl Rgba {
     }
 }
 
+impl FromStr for Rgba {
+    type Err = ();
+
+    /// Parse a color name without panicking on invalid names. Accepts the
+    /// same names as Rgba::parse.
+    fn from_str(name: &str) -> Result<Rgba, ()> {
+        parse_color(name).ok_or(())
+    }
+}
+
 impl Color for Rgba {
     fn to_rgba(&self) -> [f32; 4] {
         [self.r as f32 / 255.0,
@@ -1,61 +1,79 @@
+use std::mem;
+use std::char;
+use std::f32;
+use rgb::Rgba;
+use geom::{V2, Rect};
+
 /// Divide a string into the longest slice that fits within maximum line
-/// length and the rest of the string. Place the split before a whitespace or
-/// after a hyphen if possible. Any whitespace between the two segments is
-/// trimmed. Newlines will cause a segment split when encountered.
+/// length and the rest of the string. The split is placed at a line break
+/// opportunity as defined by the Unicode line breaking algorithm (UAX #14)
+/// if possible, such as a whitespace, after a hyphen or between CJK
+/// ideographs. Any whitespace between the two segments is trimmed. Newlines
+/// will cause a segment split when encountered.
+///
+/// Soft hyphens (U+00AD) are invisible unless the line is split at one. In
+/// that case the head segment ends with the soft hyphen and its width is
+/// measured as the width of '-'. Use `visible_text` to turn a line into the
+/// chars that should be drawn.
 pub fn split_line<'a, F>(text: &'a str, char_width: &F, max_len: f32) -> (&'a str, &'a str)
     where F: Fn(char) -> f32 {
     assert!(max_len >= 0.0);
 
     if text.len() == 0 { return (text, text); }
 
-    // Init the split position to 1 because we always want to return at least
-    // 1 character in the head partition.
-    let mut head_end = 1;
-    let mut tail_start = 1;
-    // Is the iteration currently consuming whitespace inside a possible
-    // break.
-    let mut eat_whitespace = false;
+    // Init the split position to the first char because we always want to
+    // return at least 1 character in the head partition. The positions are
+    // byte offsets into text.
+    let first_len = text.chars().next().unwrap().len_utf8();
+    let mut head_end = first_len;
+    let mut tail_start = first_len;
+    // Whether a proper break opportunity has been found.
+    let mut found = false;
+    // Break class of the last non-whitespace char, None while going through
+    // leading whitespace, which is indentation and not a break.
+    let mut prev = None;
+    // Whether there was whitespace between prev and the current char.
+    let mut after_space = false;
+    // End position and length of the text up to prev.
+    let mut word_end = 0;
+    let mut word_len = 0.0;
     let mut length = 0.0;
 
-    for (i, c) in text.chars().enumerate() {
-        length = length + char_width(c);
+    for (i, c) in text.char_indices() {
+        let class = break_class(c);
+
+        if class == BreakClass::Mandatory {
+            // Treat CR LF as a single newline.
+            let tail = if c == '\r' && text[i + 1..].starts_with("\n") { i + 2 } else { i + c.len_utf8() };
+            return (&text[..word_end], &text[tail..]);
+        }
+
+        if !is_invisible(c) { length = length + char_width(c); }
+
+        if class == BreakClass::Space {
+            if prev.is_some() { after_space = true; }
+            continue;
+        }
 
         // Invariant: head_end and tail_start describe a valid, but possibly
         // suboptimal return value at this point.
         assert!(text[..head_end].len() > 0);
         assert!(head_end <= tail_start);
 
-        if eat_whitespace {
-            if c.is_whitespace() {
-                tail_start = i + 1;
-                if c == '\n' { return (&text[..head_end], &text[tail_start..]); }
-                continue;
-            } else {
-                eat_whitespace = false;
+        if let Some(p) = prev {
+            // A line ending at a soft hyphen gets a visible hyphen that
+            // must fit on the line too.
+            if can_break(p, class, after_space) &&
+                (p != BreakClass::SoftHyphen || word_len + char_width('-') <= max_len) {
+                head_end = word_end;
+                tail_start = i;
+                found = true;
             }
         }
 
-        // We're either just encountering the first whitespace after a block
-        // of text, or over non-whitespace text.
-        assert!(!eat_whitespace);
-
-        // Invariant: The length of the string processed up to this point is
-        // still short enough to return.
-
-        // Encounter the first whitespace, set head_end marker.
-        if c.is_whitespace() {
-            head_end = i;
-            tail_start = i + 1;
-            if c == '\n' { return (&text[..head_end], &text[tail_start..]); }
-            eat_whitespace = true;
-            continue;
-        }
-
-        assert!(!c.is_whitespace());
-
         // Went over the allowed length.
         if length > max_len {
-            if i > 1 && head_end == 1 && tail_start == 1 {
+            if !found && i > first_len {
                 // Didn't encounter any better cut points, so just place cut
                 // in the middle of the word where we're at.
                 head_end = i;
@@ -66,16 +84,122 @@ pub fn split_line<'a, F>(text: &'a str, char_width: &F, max_len: f32) -> (&'a st
             return (&text[..head_end], &text[tail_start..]);
         }
 
-        // Hyphens are a possible cut point.
-        if c == '-' {
-            head_end = i + 1;
-            tail_start = i + 1;
-        }
+        prev = Some(class);
+        after_space = false;
+        word_end = i + c.len_utf8();
+        word_len = length;
     }
 
     (&text, &""[..])
 }
 
+/// Return the chars of a line produced by split_line that should be drawn.
+/// Invisible formatting chars are removed and a soft hyphen at the end of
+/// the line is turned into a visible hyphen.
+pub fn visible_text(line: &str) -> String {
+    let mut ret: String = line.chars().filter(|&c| !is_invisible(c)).collect();
+    if line.ends_with("\u{AD}") { ret.push('-'); }
+    ret
+}
+
+/// Line breaking classes from UAX #14, with the rarer ones folded into the
+/// closest common class.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum BreakClass {
+    /// Newlines, always break.
+    Mandatory,
+    Space,
+    /// Zero-width space, a break opportunity with no width.
+    ZeroWidthSpace,
+    /// No-break spaces and word joiners, prevent breaks on both sides.
+    Glue,
+    /// Hyphen-minus, allow a break after unless followed by a number.
+    Hyphen,
+    /// Dashes, allow a break after.
+    BreakAfter,
+    SoftHyphen,
+    /// Opening punctuation, no break after.
+    Open,
+    /// Closing punctuation, no break before.
+    Close,
+    /// Terminal punctuation, no break before.
+    Exclamation,
+    /// Infix separators like comma and period, no break before.
+    InfixSeparator,
+    /// Small kana and iteration marks that must not start a line.
+    Nonstarter,
+    /// CJK ideographs, kana and hangul, allow breaks on both sides.
+    Ideographic,
+    Numeric,
+    Alphabetic,
+}
+
+fn break_class(c: char) -> BreakClass {
+    use self::BreakClass::*;
+    match c {
+        '\n' | '\r' | '\x0B' | '\x0C' | '\u{85}' | '\u{2028}' | '\u{2029}' => Mandatory,
+        '\u{200B}' => ZeroWidthSpace,
+        // No-break spaces count as whitespace, so this must come before the
+        // whitespace check.
+        '\u{A0}' | '\u{2007}' | '\u{202F}' | '\u{2011}' | '\u{2060}' | '\u{FEFF}' => Glue,
+        c if c.is_whitespace() => Space,
+        '-' => Hyphen,
+        '\u{AD}' => SoftHyphen,
+        '\u{2010}' | '\u{2012}' | '\u{2013}' | '\u{2014}' => BreakAfter,
+        '(' | '[' | '{' | '\u{3008}' | '\u{300A}' | '\u{300C}' | '\u{300E}' |
+        '\u{3010}' | '\u{3014}' | '\u{3016}' | '\u{3018}' | '\u{301A}' |
+        '\u{FF08}' | '\u{FF3B}' | '\u{FF5B}' => Open,
+        ')' | ']' | '}' | '\u{3001}' | '\u{3002}' | '\u{3009}' | '\u{300B}' |
+        '\u{300D}' | '\u{300F}' | '\u{3011}' | '\u{3015}' | '\u{3017}' |
+        '\u{3019}' | '\u{301B}' | '\u{FF09}' | '\u{FF0C}' | '\u{FF0E}' |
+        '\u{FF3D}' | '\u{FF5D}' => Close,
+        '!' | '?' | '\u{FF01}' | '\u{FF1F}' => Exclamation,
+        ',' | '.' | ':' | ';' => InfixSeparator,
+        '\u{3005}' | '\u{303B}' | '\u{3041}' | '\u{3043}' | '\u{3045}' |
+        '\u{3047}' | '\u{3049}' | '\u{3063}' | '\u{3083}' | '\u{3085}' |
+        '\u{3087}' | '\u{308E}' | '\u{3095}' | '\u{3096}' | '\u{309D}' |
+        '\u{309E}' | '\u{30A1}' | '\u{30A3}' | '\u{30A5}' | '\u{30A7}' |
+        '\u{30A9}' | '\u{30C3}' | '\u{30E3}' | '\u{30E5}' | '\u{30E7}' |
+        '\u{30EE}' | '\u{30F5}' | '\u{30F6}' | '\u{30FB}' | '\u{30FC}' |
+        '\u{30FD}' | '\u{30FE}' | '\u{FF1A}' | '\u{FF1B}' => Nonstarter,
+        '\u{2E80}'...'\u{2FFF}' | '\u{3040}'...'\u{30FF}' | '\u{3100}'...'\u{31FF}' |
+        '\u{3200}'...'\u{4DBF}' | '\u{4E00}'...'\u{9FFF}' | '\u{A000}'...'\u{A4CF}' |
+        '\u{AC00}'...'\u{D7AF}' | '\u{F900}'...'\u{FAFF}' | '\u{FF01}'...'\u{FF60}' |
+        '\u{FFE0}'...'\u{FFE6}' | '\u{20000}'...'\u{3FFFD}' => Ideographic,
+        '0'...'9' => Numeric,
+        _ => Alphabetic,
+    }
+}
+
+/// Return whether a line break is allowed between two chars of the given
+/// classes, with or without whitespace between them.
+fn can_break(before: BreakClass, after: BreakClass, space_between: bool) -> bool {
+    use self::BreakClass::*;
+    match after {
+        Close | Exclamation | InfixSeparator | Glue | ZeroWidthSpace => { return false; }
+        _ => {}
+    }
+    if before == ZeroWidthSpace { return true; }
+    // Opening punctuation sticks to the next word even across spaces.
+    if before == Open { return false; }
+    if space_between { return true; }
+    match after {
+        Nonstarter | Hyphen | BreakAfter | SoftHyphen => { return false; }
+        _ => {}
+    }
+    match before {
+        Glue => false,
+        // Don't separate the minus sign from a negative number.
+        Hyphen => after != Numeric,
+        BreakAfter | SoftHyphen => true,
+        _ => before == Ideographic || after == Ideographic
+    }
+}
+
+/// Formatting chars that are not drawn.
+fn is_invisible(c: char) -> bool {
+    c == '\u{AD}' || c == '\u{200B}' || c == '\u{2060}' || c == '\u{FEFF}'
+}
 
 pub fn wrap_lines<F>(mut text: &str, char_width: &F, max_len: f32) -> String
     where F: Fn(char) -> f32 {
@@ -83,9 +207,8 @@ pub fn wrap_lines<F>(mut text: &str, char_width: &F, max_len: f32) -> String
     loop {
         let (head, tail) = split_line(text, char_width, max_len);
         if head.len() == 0 && tail.len() == 0 { break; }
-        assert!(head.len() > 0, "Line splitter caught in infinite loop");
         assert!(tail.len() < text.len(), "Line splitter not shrinking string");
-        result = result + head;
+        result = result + &visible_text(head)[..];
         // Must preserve a hard newline at the end if the input string had
         // one. The else branch checks for the very last char being a newline,
         // this would be clipped off otherwise.
@@ -96,6 +219,582 @@ pub fn wrap_lines<F>(mut text: &str, char_width: &F, max_len: f32) -> String
     result
 }
 
+/// Style of a run of marked up text.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Style {
+    /// Text color, None for the default color of the text block.
+    pub color: Option<Rgba>,
+    /// Whether the text is emphasized.
+    pub emphasis: bool,
+}
+
+/// A piece of marked up text with a single style.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Run {
+    Text(String, Style),
+    /// Named icon image drawn inline with the text.
+    Icon(String, Style),
+}
+
+/// Parse text with inline markup into styled runs. The markup tags are
+/// `{colorname}` or `{#rgb}` to change the text color, `{em}` for emphasis,
+/// `{icon:name}` for an inline icon and `{/}` to end the innermost color or
+/// emphasis tag. Literal braces are written as `{{` and `}}`.
+pub fn parse_markup(text: &str) -> Result<Vec<Run>, String> {
+    let mut ret = Vec::new();
+    let mut styles = vec![Style::default()];
+    let mut buf = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if (c == '{' || c == '}') && chars.peek() == Some(&c) {
+            // Escaped brace.
+            chars.next();
+            buf.push(c);
+            continue;
+        }
+
+        if c != '{' {
+            buf.push(c);
+            continue;
+        }
+
+        let mut tag = String::new();
+        loop {
+            match chars.next() {
+                Some('}') => { break; }
+                Some(c) => { tag.push(c); }
+                None => { return Err(format!("Unterminated tag {{{}", tag)); }
+            }
+        }
+
+        let style = *styles.last().unwrap();
+        flush(&mut ret, &mut buf, style);
+        if &tag[..] == "/" {
+            if styles.len() == 1 { return Err("Unmatched {/}".to_string()); }
+            styles.pop();
+        } else if &tag[..] == "em" {
+            styles.push(Style { emphasis: true, ..style });
+        } else if tag.starts_with("icon:") {
+            ret.push(Run::Icon(tag[5..].to_string(), style));
+        } else {
+            match tag.parse::<Rgba>() {
+                Ok(color) => { styles.push(Style { color: Some(color), ..style }); }
+                Err(_) => { return Err(format!("Unknown tag {{{}}}", tag)); }
+            }
+        }
+    }
+
+    // Unclosed tags are fine, they just run to the end of the text.
+    let style = *styles.last().unwrap();
+    flush(&mut ret, &mut buf, style);
+    return Ok(ret);
+
+    fn flush(runs: &mut Vec<Run>, buf: &mut String, style: Style) {
+        if buf.len() > 0 {
+            runs.push(Run::Text(mem::replace(buf, String::new()), style));
+        }
+    }
+}
+
+/// Icons are stood in for by chars from the supplementary private use area
+/// when wrapping runs.
+static ICON_BASE: u32 = 0xF0000;
+
+/// Wrap styled runs into lines that fit within max_len, breaking them the
+/// same way as wrap_lines. Icons are unbreakable and their widths are given
+/// by icon_width.
+pub fn wrap_runs<F, G>(runs: &[Run], char_width: &F, icon_width: &G, max_len: f32) -> Vec<Vec<Run>>
+    where F: Fn(char) -> f32, G: Fn(&str) -> f32 {
+    // Flatten the runs into a plain string with placeholder chars for icons
+    // so that split_line can process it.
+    let mut flat = String::new();
+    let mut icons: Vec<&str> = Vec::new();
+    // Style of every byte in flat.
+    let mut styles = Vec::new();
+    for run in runs.iter() {
+        match *run {
+            Run::Text(ref s, style) => {
+                flat.push_str(&s[..]);
+                for _ in 0..s.len() { styles.push(style); }
+            }
+            Run::Icon(ref name, style) => {
+                let c = char::from_u32(ICON_BASE + icons.len() as u32).expect("Too many icons");
+                icons.push(&name[..]);
+                flat.push(c);
+                for _ in 0..c.len_utf8() { styles.push(style); }
+            }
+        }
+    }
+
+    let width = |c: char| match icon_index(c, icons.len()) {
+        Some(i) => icon_width(icons[i]),
+        None => char_width(c)
+    };
+
+    let mut ret = Vec::new();
+    let mut text = &flat[..];
+    while text.len() > 0 {
+        let (head, tail) = split_line(text, &width, max_len);
+        assert!(tail.len() < text.len(), "Line splitter not shrinking string");
+        ret.push(unflatten(head, flat.len() - text.len(), &styles[..], &icons[..]));
+        // Preserve a hard newline at the very end as an empty last line.
+        if tail.len() == 0 && text.chars().last() == Some('\n') { ret.push(Vec::new()); }
+        text = tail;
+    }
+    return ret;
+
+    fn icon_index(c: char, num_icons: usize) -> Option<usize> {
+        let c = c as u32;
+        if c >= ICON_BASE && c < ICON_BASE + num_icons as u32 {
+            Some((c - ICON_BASE) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Turn a line of flattened text back into runs. Offset is the position
+    /// of the line in the flattened text.
+    fn unflatten(line: &str, offset: usize, styles: &[Style], icons: &[&str]) -> Vec<Run> {
+        let mut ret = Vec::new();
+        for (i, c) in line.char_indices() {
+            let style = styles[offset + i];
+            // Only a soft hyphen at the end of the line is drawn.
+            let c = if c == '\u{AD}' && i + c.len_utf8() == line.len() { '-' } else { c };
+            if is_invisible(c) { continue; }
+            if let Some(idx) = icon_index(c, icons.len()) {
+                ret.push(Run::Icon(icons[idx].to_string(), style));
+                continue;
+            }
+            // Extend the previous run if the style stays the same.
+            if let Some(&mut Run::Text(ref mut s, run_style)) = ret.last_mut() {
+                if run_style == style {
+                    s.push(c);
+                    continue;
+                }
+            }
+            ret.push(Run::Text(c.to_string(), style));
+        }
+        ret
+    }
+}
+
+/// Font measurements needed to lay out text.
+pub trait FontMetrics {
+    /// Distance between the baselines of successive lines.
+    fn line_height(&self) -> f32;
+
+    /// Distance from the top of a line to the baseline.
+    fn ascent(&self) -> f32;
+
+    /// How much the pen advances after drawing a char.
+    fn char_width(&self, c: char) -> f32;
+
+    /// Extra horizontal adjustment between two successive chars.
+    fn kerning(&self, _left: char, _right: char) -> f32 { 0.0 }
+
+    /// Width of a named inline icon.
+    fn icon_width(&self, _name: &str) -> f32 { 0.0 }
+}
+
+/// Horizontal alignment of text lines.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+    /// Left for left-to-right text, right for right-to-left text.
+    Start,
+    /// Right for left-to-right text, left for right-to-left text.
+    End,
+}
+
+impl Align {
+    /// Turn Start and End into Left or Right for the text direction.
+    pub fn resolve(self, direction: Direction) -> Align {
+        match (self, direction) {
+            (Align::Start, Direction::LeftToRight) | (Align::End, Direction::RightToLeft) => Align::Left,
+            (Align::Start, Direction::RightToLeft) | (Align::End, Direction::LeftToRight) => Align::Right,
+            (a, _) => a
+        }
+    }
+}
+
+/// Base direction of a paragraph of text.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Direction {
+    LeftToRight,
+    RightToLeft,
+}
+
+/// Bidirectional character types from the Unicode Bidirectional Algorithm
+/// (UAX #9). The explicit embedding and isolate types are left out.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum BidiClass {
+    /// Strong left-to-right.
+    L,
+    /// Strong right-to-left.
+    R,
+    /// Arabic letter.
+    AL,
+    /// European number.
+    EN,
+    /// European number separator.
+    ES,
+    /// European number terminator.
+    ET,
+    /// Arabic number.
+    AN,
+    /// Common number separator.
+    CS,
+    /// Non-spacing mark.
+    NSM,
+    /// Paragraph separator.
+    B,
+    /// Segment separator.
+    S,
+    /// Whitespace.
+    WS,
+    /// Other neutral.
+    ON,
+}
+
+fn bidi_class(c: char) -> BidiClass {
+    use self::BidiClass::*;
+    match c {
+        '\n' | '\r' | '\u{1C}'...'\u{1E}' | '\u{85}' | '\u{2029}' => B,
+        '\t' | '\u{0B}' | '\u{1F}' => S,
+        '0'...'9' | '\u{B2}' | '\u{B3}' | '\u{B9}' | '\u{6F0}'...'\u{6F9}' => EN,
+        '\u{660}'...'\u{669}' | '\u{66B}' | '\u{66C}' => AN,
+        '+' | '-' | '\u{2212}' => ES,
+        '#' | '$' | '%' | '\u{A2}'...'\u{A5}' | '\u{B0}' | '\u{B1}' |
+        '\u{20A0}'...'\u{20CF}' => ET,
+        ',' | '.' | '/' | ':' | '\u{A0}' => CS,
+        '\u{300}'...'\u{36F}' | '\u{591}'...'\u{5BD}' | '\u{5BF}' | '\u{5C1}' |
+        '\u{5C2}' | '\u{5C4}' | '\u{5C5}' | '\u{5C7}' | '\u{610}'...'\u{61A}' |
+        '\u{64B}'...'\u{65F}' | '\u{670}' | '\u{6D6}'...'\u{6DC}' |
+        '\u{6DF}'...'\u{6E4}' | '\u{6E7}' | '\u{6E8}' | '\u{6EA}'...'\u{6ED}' => NSM,
+        '\u{590}'...'\u{5FF}' | '\u{7C0}'...'\u{85F}' | '\u{FB1D}'...'\u{FB4F}' => R,
+        '\u{600}'...'\u{6FF}' | '\u{700}'...'\u{7BF}' | '\u{860}'...'\u{8FF}' |
+        '\u{FB50}'...'\u{FDFF}' | '\u{FE70}'...'\u{FEFE}' => AL,
+        c if c.is_whitespace() => WS,
+        c if c.is_alphabetic() => L,
+        _ => ON,
+    }
+}
+
+/// Return the direction of the first strongly directional char in the
+/// text, or None if there are none.
+pub fn text_direction(text: &str) -> Option<Direction> {
+    for c in text.chars() {
+        match bidi_class(c) {
+            BidiClass::L => { return Some(Direction::LeftToRight); }
+            BidiClass::R | BidiClass::AL => { return Some(Direction::RightToLeft); }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Resolve the bidi embedding levels of the chars of a line of text with
+/// the given paragraph direction. Even levels are left-to-right and odd
+/// levels right-to-left. Explicit embedding and isolate formatting chars
+/// and bracket pairs are not handled specially.
+pub fn bidi_levels(line: &[char], direction: Direction) -> Vec<u8> {
+    use self::BidiClass::*;
+
+    let base = match direction { Direction::LeftToRight => 0, Direction::RightToLeft => 1 };
+    // Start and end of sequence type.
+    let sos = if base == 0 { L } else { R };
+    let original: Vec<BidiClass> = line.iter().map(|&c| bidi_class(c)).collect();
+    let mut t = original.clone();
+    let n = t.len();
+
+    // W1: Non-spacing marks take the type of the previous char.
+    for i in 0..n {
+        if t[i] == NSM { t[i] = if i == 0 { sos } else { t[i - 1] }; }
+    }
+
+    // W2: European numbers after Arabic letters are Arabic numbers.
+    let mut strong = sos;
+    for i in 0..n {
+        let c = t[i];
+        match c {
+            L | R | AL => { strong = c; }
+            EN if strong == AL => { t[i] = AN; }
+            _ => {}
+        }
+    }
+
+    // W3: Arabic letters are plain right-to-left from here on.
+    for i in 0..n { if t[i] == AL { t[i] = R; } }
+
+    // W4: A single separator between two numbers of the same type joins
+    // them.
+    for i in 1..n {
+        if i + 1 >= n { break; }
+        let (prev, next) = (t[i - 1], t[i + 1]);
+        if t[i] == ES && prev == EN && next == EN {
+            t[i] = EN;
+        } else if t[i] == CS && prev == next && (prev == EN || prev == AN) {
+            t[i] = prev;
+        }
+    }
+
+    // W5: Terminators next to European numbers are part of the number.
+    let mut i = 0;
+    while i < n {
+        if t[i] != ET { i += 1; continue; }
+        let start = i;
+        while i < n && t[i] == ET { i += 1; }
+        if (start > 0 && t[start - 1] == EN) || (i < n && t[i] == EN) {
+            for j in start..i { t[j] = EN; }
+        }
+    }
+
+    // W6: Remaining separators and terminators are neutral.
+    for i in 0..n {
+        match t[i] { ES | ET | CS => { t[i] = ON; } _ => {} }
+    }
+
+    // W7: European numbers in left-to-right context are left-to-right.
+    let mut strong = sos;
+    for i in 0..n {
+        let c = t[i];
+        match c {
+            L | R => { strong = c; }
+            EN if strong == L => { t[i] = L; }
+            _ => {}
+        }
+    }
+
+    // N1, N2: Neutrals between two chars of the same direction take that
+    // direction, otherwise they take the paragraph direction. Numbers count
+    // as right-to-left here.
+    let mut i = 0;
+    while i < n {
+        if !is_neutral(t[i]) { i += 1; continue; }
+        let start = i;
+        while i < n && is_neutral(t[i]) { i += 1; }
+        let before = if start == 0 { sos } else { strong_direction(t[start - 1]) };
+        let after = if i == n { sos } else { strong_direction(t[i]) };
+        let dir = if before == after { before } else { sos };
+        for j in start..i { t[j] = dir; }
+    }
+
+    // I1, I2: Resolve the implicit levels.
+    let mut levels: Vec<u8> = t.iter().map(|&c| {
+        match (base, c) {
+            (0, R) => 1,
+            (0, AN) | (0, EN) => 2,
+            (0, _) => 0,
+            (_, R) => 1,
+            _ => 2,
+        }
+    }).collect();
+
+    // L1: Separators and trailing whitespace go back to the paragraph
+    // level.
+    let mut trailing = true;
+    for i in (0..n).rev() {
+        match original[i] {
+            B | S => { levels[i] = base; trailing = true; }
+            WS if trailing => { levels[i] = base; }
+            _ => { trailing = false; }
+        }
+    }
+
+    return levels;
+
+    fn is_neutral(c: BidiClass) -> bool {
+        match c { B | S | WS | ON => true, _ => false }
+    }
+
+    fn strong_direction(c: BidiClass) -> BidiClass {
+        if c == L { L } else { R }
+    }
+}
+
+/// Return the indices of the chars of a line in left-to-right visual
+/// order given their bidi levels.
+pub fn visual_order(levels: &[u8]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..levels.len()).collect();
+    let max = levels.iter().cloned().max().unwrap_or(0);
+    let min_odd = match levels.iter().cloned().filter(|&l| l % 2 == 1).min() {
+        Some(l) => l,
+        None => { return order; }
+    };
+
+    // L2: From the highest level down to the lowest odd level, reverse
+    // every sequence of chars at that level or higher.
+    for level in (min_odd..max + 1).rev() {
+        let mut i = 0;
+        while i < order.len() {
+            if levels[order[i]] < level { i += 1; continue; }
+            let start = i;
+            while i < order.len() && levels[order[i]] >= level { i += 1; }
+            order[start..i].reverse();
+        }
+    }
+    order
+}
+
+/// Return the mirror image of a bracket-like char, for drawing it in
+/// right-to-left text.
+pub fn mirror(c: char) -> char {
+    match c {
+        '(' => ')', ')' => '(',
+        '[' => ']', ']' => '[',
+        '{' => '}', '}' => '{',
+        '<' => '>', '>' => '<',
+        '\u{AB}' => '\u{BB}', '\u{BB}' => '\u{AB}',
+        '\u{2039}' => '\u{203A}', '\u{203A}' => '\u{2039}',
+        c => c
+    }
+}
+
+/// A drawable element of laid out text.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Glyph {
+    Char(char),
+    /// Named inline icon.
+    Icon(String),
+}
+
+/// A glyph positioned by text layout.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PlacedGlyph {
+    pub glyph: Glyph,
+    /// Pen position on the baseline relative to the top left corner of the
+    /// text box.
+    pub pos: V2<f32>,
+    pub style: Style,
+}
+
+/// A line of laid out text.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Line {
+    /// Index of the first glyph of the line in the layout glyph list.
+    pub start: usize,
+    /// Index one past the last glyph of the line.
+    pub end: usize,
+    pub width: f32,
+}
+
+/// Text laid out into lines of positioned glyphs.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Layout {
+    pub lines: Vec<Line>,
+    pub glyphs: Vec<PlacedGlyph>,
+    /// Bounding box of the text, with the top left corner at origin.
+    pub bounds: Rect<f32>,
+    /// Base direction of the text.
+    pub direction: Direction,
+}
+
+/// Lay out styled text runs. If max_width is given, the text is wrapped to
+/// fit within it. If max_lines is given, only that many lines from the end
+/// of the text are kept. If direction is None, the base direction is
+/// detected from the first strongly directional char of the text.
+///
+/// Bidirectional text is reordered for display line by line. The glyphs are
+/// listed in the logical order of the text, but positioned in visual order.
+pub fn layout<M: FontMetrics>(
+    metrics: &M, runs: &[Run], max_width: Option<f32>, align: Align,
+    direction: Option<Direction>, max_lines: Option<usize>) -> Layout {
+    let direction = direction.unwrap_or_else(|| {
+        let mut ret = Direction::LeftToRight;
+        for run in runs.iter() {
+            if let Run::Text(ref txt, _) = *run {
+                if let Some(dir) = text_direction(&txt[..]) { ret = dir; break; }
+            }
+        }
+        ret
+    });
+
+    let mut lines = wrap_runs(
+        runs,
+        &|c| metrics.char_width(c),
+        &|name: &str| metrics.icon_width(name),
+        max_width.unwrap_or(f32::INFINITY));
+    if lines.is_empty() { lines.push(Vec::new()); }
+    if let Some(n) = max_lines {
+        if lines.len() > n {
+            let drop = lines.len() - n;
+            lines = lines.split_off(drop);
+        }
+    }
+
+    // Lay out the glyphs of each line starting from x = 0 and shift them
+    // for alignment once the longest line is known.
+    let mut ret = Layout {
+        lines: Vec::new(),
+        glyphs: Vec::new(),
+        bounds: Rect(V2(0.0, 0.0), V2(0.0, lines.len() as f32 * metrics.line_height())),
+        direction: direction,
+    };
+
+    for (row, line) in lines.iter().enumerate() {
+        let y = metrics.ascent() + row as f32 * metrics.line_height();
+        let start = ret.glyphs.len();
+
+        // Icons are stood in for by the object replacement char for bidi
+        // resolution.
+        let mut chars = Vec::new();
+        for run in line.iter() {
+            match *run {
+                Run::Text(ref txt, style) => {
+                    for c in txt.chars() {
+                        ret.glyphs.push(PlacedGlyph { glyph: Glyph::Char(c), pos: V2(0.0, y), style: style });
+                        chars.push(c);
+                    }
+                }
+                Run::Icon(ref name, style) => {
+                    ret.glyphs.push(PlacedGlyph { glyph: Glyph::Icon(name.clone()), pos: V2(0.0, y), style: style });
+                    chars.push('\u{FFFC}');
+                }
+            }
+        }
+
+        let levels = bidi_levels(&chars[..], direction);
+        let mut x = 0.0;
+        let mut prev = None;
+        for &i in visual_order(&levels[..]).iter() {
+            let g = &mut ret.glyphs[start + i];
+            g.pos.0 = x;
+            match g.glyph {
+                Glyph::Char(ref mut c) => {
+                    if levels[i] % 2 == 1 { *c = mirror(*c); }
+                    if let Some(p) = prev { x += metrics.kerning(p, *c); }
+                    g.pos.0 = x;
+                    x += metrics.char_width(*c);
+                    prev = Some(*c);
+                }
+                Glyph::Icon(ref name) => {
+                    x += metrics.icon_width(&name[..]);
+                    prev = None;
+                }
+            }
+        }
+        ret.lines.push(Line { start: start, end: ret.glyphs.len(), width: x });
+        if x > (ret.bounds.1).0 { (ret.bounds.1).0 = x; }
+    }
+
+    let longest = (ret.bounds.1).0;
+    for line in ret.lines.iter() {
+        let shift = match align.resolve(direction) {
+            Align::Right => longest - line.width,
+            Align::Center => (longest - line.width) / 2.0,
+            _ => 0.0,
+        };
+        for g in ret.glyphs[line.start..line.end].iter_mut() {
+            g.pos.0 = g.pos.0 + shift;
+        }
+    }
+
+    ret
+}
+
 pub struct Map2DIterator<T> {
     /// Input iterator
     iter: T,
@@ -147,5 +846,170 @@ mod test {
         assert_eq!(("a", "bc"), split_line("abc", &|_| 1.0, 0.01));
         assert_eq!(("dead", "beef"), split_line("deadbeef", &|_| 1.0, 4.0));
         assert_eq!(("the-", "cat"), split_line("the-cat", &|_| 1.0, 5.0));
+        assert_eq!(("  the", "cat"), split_line("  the cat", &|_| 1.0, 7.0));
+        assert_eq!(("äö", "ü"), split_line("äöü", &|_| 1.0, 2.0));
+        assert_eq!(("", "cat"), split_line("\ncat", &|_| 1.0, 32.0));
+        assert_eq!(("the", "cat"), split_line("the\r\ncat", &|_| 1.0, 32.0));
+        assert_eq!(("漢字", "かな"), split_line("漢字かな", &|_| 1.0, 2.0));
+        assert_eq!(("漢", "字。漢字"), split_line("漢字。漢字", &|_| 1.0, 2.0));
+        assert_eq!(("「漢", "字」です"), split_line("「漢字」です", &|_| 1.0, 3.0));
+        assert_eq!(("ちょっ", "と"), split_line("ちょっと", &|_| 1.0, 3.0));
+        assert_eq!(("the", "(cat)"), split_line("the (cat)", &|_| 1.0, 6.0));
+        assert_eq!(("10\u{A0}km", "away"), split_line("10\u{A0}km away", &|_| 1.0, 5.0));
+        assert_eq!(("x", "-5"), split_line("x -5", &|_| 1.0, 2.0));
+        assert_eq!(("dead\u{AD}", "beef"), split_line("dead\u{AD}beef", &|_| 1.0, 6.0));
+        assert_eq!(("foo\u{200B}", "bar"), split_line("foo\u{200B}bar", &|_| 1.0, 4.0));
+    }
+
+    #[test]
+    fn test_wrap_lines() {
+        use super::wrap_lines;
+
+        assert_eq!("the\ncat\nsat", wrap_lines("the cat sat", &|_| 1.0, 5.0));
+        assert_eq!("\ncat", wrap_lines("\ncat", &|_| 1.0, 5.0));
+        assert_eq!("dead-\nbeef", wrap_lines("dead\u{AD}beef", &|_| 1.0, 6.0));
+        assert_eq!("deadbeef", wrap_lines("dead\u{AD}beef", &|_| 1.0, 10.0));
+        assert_eq!("漢字\nかな", wrap_lines("漢字かな", &|_| 1.0, 2.0));
+    }
+
+    #[test]
+    fn test_parse_markup() {
+        use super::{parse_markup, Run, Style};
+        use rgb::Rgba;
+
+        let red = Style { color: Some(Rgba::new(0xff, 0, 0, 0xff)), emphasis: false };
+        assert_eq!(Ok(vec![]), parse_markup(""));
+        assert_eq!(Ok(vec![Run::Text("{a}".to_string(), Style::default())]),
+                   parse_markup("{{a}}"));
+        assert_eq!(Ok(vec![
+            Run::Text("You hit the ".to_string(), Style::default()),
+            Run::Text("goblin".to_string(), red),
+            Run::Text(" for 5 ".to_string(), Style::default()),
+            Run::Icon("heart".to_string(), Style::default())]),
+            parse_markup("You hit the {red}goblin{/} for 5 {icon:heart}"));
+        assert_eq!(Ok(vec![
+            Run::Text("a".to_string(), red),
+            Run::Text("b".to_string(), Style { emphasis: true, ..red })]),
+            parse_markup("{#f00}a{em}b"));
+        assert!(parse_markup("{/}").is_err());
+        assert!(parse_markup("{red").is_err());
+        assert!(parse_markup("{notacolor}x{/}").is_err());
+    }
+
+    #[test]
+    fn test_wrap_runs() {
+        use super::{wrap_runs, Run, Style};
+
+        let plain = Style::default();
+        let em = Style { emphasis: true, ..plain };
+        let runs = vec![
+            Run::Text("the ".to_string(), plain),
+            Run::Text("cat".to_string(), em),
+            Run::Text(" sat ".to_string(), plain),
+            Run::Icon("mat".to_string(), plain),
+            Run::Text("\n".to_string(), plain)];
+        assert_eq!(vec![
+            vec![Run::Text("the ".to_string(), plain),
+                 Run::Text("cat".to_string(), em)],
+            vec![Run::Text("sat".to_string(), plain)],
+            vec![Run::Icon("mat".to_string(), plain)],
+            vec![]],
+            wrap_runs(&runs[..], &|_| 1.0, &|_| 5.0, 7.0));
+    }
+
+    struct TestMetrics;
+
+    impl super::FontMetrics for TestMetrics {
+        fn line_height(&self) -> f32 { 10.0 }
+        fn ascent(&self) -> f32 { 8.0 }
+        fn char_width(&self, c: char) -> f32 { if c == 'm' { 2.0 } else { 1.0 } }
+        fn kerning(&self, left: char, right: char) -> f32 {
+            if left == 'a' && right == 'b' { -0.5 } else { 0.0 }
+        }
+        fn icon_width(&self, _name: &str) -> f32 { 3.0 }
+    }
+
+    #[test]
+    fn test_layout() {
+        use super::{layout, parse_markup, Align, Glyph, Line};
+        use geom::{V2, Rect};
+
+        let runs = parse_markup("ab m\nxy{icon:z}").unwrap();
+        let text = layout(&TestMetrics, &runs[..], None, Align::Left, None, None);
+        assert_eq!(vec![Line { start: 0, end: 4, width: 4.5 }, Line { start: 4, end: 7, width: 5.0 }],
+                   text.lines);
+        assert_eq!(Rect(V2(0.0, 0.0), V2(5.0, 20.0)), text.bounds);
+        assert_eq!(Glyph::Char('b'), text.glyphs[1].glyph);
+        assert_eq!(V2(0.5, 8.0), text.glyphs[1].pos);
+        assert_eq!(Glyph::Icon("z".to_string()), text.glyphs[6].glyph);
+        assert_eq!(V2(2.0, 18.0), text.glyphs[6].pos);
+
+        let text = layout(&TestMetrics, &runs[..], None, Align::Right, None, None);
+        assert_eq!(V2(0.5, 8.0), text.glyphs[0].pos);
+        assert_eq!(V2(0.0, 18.0), text.glyphs[4].pos);
+
+        let text = layout(&TestMetrics, &runs[..], Some(2.0), Align::Center, None, Some(2));
+        assert_eq!(2, text.lines.len());
+        assert_eq!(Glyph::Char('x'), text.glyphs[0].glyph);
+        assert_eq!(V2(0.5, 8.0), text.glyphs[0].pos);
+        assert_eq!(V2(0.0, 18.0), text.glyphs[2].pos);
+        assert_eq!(Rect(V2(0.0, 0.0), V2(3.0, 20.0)), text.bounds);
+
+        let text = layout(&TestMetrics, &[], None, Align::Left, None, None);
+        assert_eq!(vec![Line { start: 0, end: 0, width: 0.0 }], text.lines);
+    }
+
+    #[test]
+    fn test_bidi() {
+        use super::{text_direction, bidi_levels, visual_order, Direction};
+        use super::Direction::*;
+
+        fn reorder(text: &str, dir: Direction) -> String {
+            let chars: Vec<char> = text.chars().collect();
+            let levels = bidi_levels(&chars[..], dir);
+            visual_order(&levels[..]).iter().map(|&i| chars[i]).collect()
+        }
+
+        assert_eq!(None, text_direction("123 !"));
+        assert_eq!(Some(LeftToRight), text_direction("1 abc אב"));
+        assert_eq!(Some(RightToLeft), text_direction("(אב) abc"));
+        assert_eq!(Some(RightToLeft), text_direction("سلام"));
+
+        assert_eq!("abc", reorder("abc", LeftToRight));
+        assert_eq!("abc", reorder("abc", RightToLeft));
+        assert_eq!("גבא", reorder("אבג", LeftToRight));
+        assert_eq!("a גבא d", reorder("a אבג d", LeftToRight));
+        // Numbers keep their left-to-right order inside right-to-left text.
+        assert_eq!("12 בא", reorder("אב 12", RightToLeft));
+        assert_eq!("a b גבא", reorder("אבג a b", RightToLeft));
+        // Trailing whitespace stays at the end of the line.
+        assert_eq!(vec![1, 1, 0], bidi_levels(&['א', 'ב', ' '], LeftToRight));
+        assert_eq!(vec![2, 2, 1, 1], bidi_levels(&['a', 'b', ' ', 'א'], RightToLeft));
+    }
+
+    #[test]
+    fn test_layout_rtl() {
+        use super::{layout, parse_markup, Align, Glyph};
+        use geom::V2;
+
+        let runs = parse_markup("אב (c)").unwrap();
+        let text = layout(&TestMetrics, &runs[..], None, Align::Start, None, None);
+        assert_eq!(super::Direction::RightToLeft, text.direction);
+        // Glyphs stay in logical order, but are placed right to left.
+        assert_eq!(Glyph::Char('א'), text.glyphs[0].glyph);
+        assert_eq!(V2(5.0, 8.0), text.glyphs[0].pos);
+        assert_eq!(V2(4.0, 8.0), text.glyphs[1].pos);
+        // Brackets in right-to-left context are mirrored.
+        assert_eq!(Glyph::Char(')'), text.glyphs[3].glyph);
+        assert_eq!(V2(2.0, 8.0), text.glyphs[3].pos);
+        assert_eq!(Glyph::Char('('), text.glyphs[5].glyph);
+        assert_eq!(V2(0.0, 8.0), text.glyphs[5].pos);
+
+        // Start alignment goes right for right-to-left text.
+        let runs = parse_markup("אב\nג").unwrap();
+        let text = layout(&TestMetrics, &runs[..], None, Align::Start, None, None);
+        assert_eq!(V2(1.0, 18.0), text.glyphs[2].pos);
+        let text = layout(&TestMetrics, &runs[..], None, Align::End, None, None);
+        assert_eq!(V2(0.0, 18.0), text.glyphs[2].pos);
     }
 }
@@ -0,0 +1,472 @@
+/*!
+Importer for maps made with the Tiled map editor.
+
+Reads the JSON map format of Tiled, see
+http://doc.mapeditor.org/reference/json-map-format/ for the description.
+Finite orthogonal maps with uncompressed tile layer data are supported.
+
+Tilesets are usually cut from a single image. Image collection tilesets,
+where every tile has an image file of its own, are read too, but their
+tiles can't be cut with `Tileset::push_tiles`. Load the images listed in
+`Tileset::tile_images` separately instead.
+ */
+
+use std::collections::HashMap;
+use rustc_serialize::json::Json;
+use rustc_serialize::base64::FromBase64;
+use image::{GenericImage, SubImage, Pixel};
+use atlas::AtlasBuilder;
+use geom::{V2, Rect};
+use tilemap::TileMap;
+
+/// Tile value bits that tell if the tile is flipped horizontally,
+/// vertically or diagonally.
+pub const FLIP_FLAGS: u32 = 0xe0000000;
+
+/// Return the global tile id of a tile value with the flip flags removed.
+pub fn gid(tile: u32) -> u32 { tile & !FLIP_FLAGS }
+
+/// A map loaded from Tiled.
+#[derive(Clone, Debug)]
+pub struct TiledMap {
+    /// The tile layers of the map. The tile values are the global tile ids
+    /// of the tilesets with the flip flags in the top bits, 0 is an empty
+    /// cell.
+    pub tiles: TileMap<u32>,
+    /// Names of the tile layers.
+    pub layer_names: Vec<String>,
+    /// Size of the map grid cells in pixels.
+    pub tile_size: V2<i32>,
+    pub tilesets: Vec<Tileset>,
+    pub object_layers: Vec<ObjectLayer>,
+    pub properties: HashMap<String, String>,
+}
+
+/// A set of tiles cut from a single image, or an image collection with a
+/// separate image for each tile.
+#[derive(Clone, Debug)]
+pub struct Tileset {
+    /// Global tile id of the first tile in the set.
+    pub first_gid: u32,
+    /// File name of an external tileset. An external tileset only has the
+    /// first gid set and needs to be loaded from the file with
+    /// Tileset::parse.
+    pub source: Option<String>,
+    pub name: String,
+    /// File name of the tileset image. None for image collection
+    /// tilesets.
+    pub image: Option<String>,
+    pub image_size: V2<i32>,
+    pub tile_size: V2<i32>,
+    /// Space around the tiles at the edges of the image.
+    pub margin: i32,
+    /// Space between the tiles in the image.
+    pub spacing: i32,
+    /// Tiles per row in the tileset image, 0 for image collection
+    /// tilesets.
+    pub columns: i32,
+    pub tile_count: u32,
+    /// The images of the tiles of an image collection tileset by tile
+    /// index.
+    pub tile_images: HashMap<u32, TileImage>,
+    pub properties: HashMap<String, String>,
+}
+
+/// The image of a single tile in an image collection tileset.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TileImage {
+    /// File name of the image.
+    pub image: String,
+    pub size: V2<i32>,
+}
+
+/// A layer of free-form objects.
+#[derive(Clone, Debug)]
+pub struct ObjectLayer {
+    pub name: String,
+    pub objects: Vec<Object>,
+    pub properties: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Object {
+    pub id: u32,
+    pub name: String,
+    /// The user-defined type string of the object.
+    pub kind: String,
+    /// Object bounds in pixels. Tiled places tile objects by their bottom
+    /// left corner, for other objects the position is the top left corner.
+    pub bounds: Rect<i32>,
+    /// Tile value if this is a tile object.
+    pub gid: Option<u32>,
+    pub properties: HashMap<String, String>,
+}
+
+impl TiledMap {
+    /// Parse a map in the Tiled JSON format.
+    pub fn parse(text: &str) -> Result<TiledMap, String> {
+        let json = try!(Json::from_str(text).map_err(|e| format!("{:?}", e)));
+
+        if json.find("infinite").and_then(|x| x.as_boolean()) == Some(true) {
+            return Err("Infinite maps are not supported".to_string());
+        }
+        let size = V2(try!(int(&json, "width")), try!(int(&json, "height")));
+        let mut ret = TiledMap {
+            tiles: TileMap::new(size, 0),
+            layer_names: Vec::new(),
+            tile_size: V2(try!(int(&json, "tilewidth")), try!(int(&json, "tileheight"))),
+            tilesets: Vec::new(),
+            object_layers: Vec::new(),
+            properties: try!(properties(&json)),
+        };
+
+        for ts in try!(array(&json, "tilesets")).iter() {
+            let first_gid = try!(int(ts, "firstgid")) as u32;
+            let tileset = match ts.find("source").and_then(|x| x.as_string()) {
+                Some(source) => Tileset::external(first_gid, source),
+                None => try!(Tileset::from_json(ts, first_gid))
+            };
+            ret.tilesets.push(tileset);
+        }
+
+        try!(ret.add_layers(try!(array(&json, "layers"))));
+        Ok(ret)
+    }
+
+    fn add_layers(&mut self, layers: &[Json]) -> Result<(), String> {
+        let size = self.tiles.size();
+        for layer in layers.iter() {
+            let name = string(layer, "name");
+            match &try!(str_field(layer, "type"))[..] {
+                "tilelayer" => {
+                    let data = try!(tile_data(layer, (size.0 * size.1) as usize));
+                    // The map starts out with one empty layer for the first
+                    // tile layer.
+                    let idx = if self.layer_names.len() == 0 { 0 } else { self.tiles.add_layer(0) };
+                    let tiles = self.tiles.layer_mut(idx);
+                    for (i, &t) in data.iter().enumerate() {
+                        tiles.set(V2(i as i32 % size.0, i as i32 / size.0), t);
+                    }
+                    self.layer_names.push(name);
+                }
+                "objectgroup" => {
+                    let mut objects = Vec::new();
+                    for obj in try!(array(layer, "objects")).iter() {
+                        objects.push(try!(object(obj)));
+                    }
+                    self.object_layers.push(ObjectLayer {
+                        name: name,
+                        objects: objects,
+                        properties: try!(properties(layer)),
+                    });
+                }
+                "group" => { try!(self.add_layers(try!(array(layer, "layers")))); }
+                // Image layers and anything else.
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the tileset and the index in the tileset for a tile value.
+    pub fn tileset(&self, tile: u32) -> Option<(&Tileset, u32)> {
+        let gid = gid(tile);
+        if gid == 0 { return None; }
+        self.tilesets.iter().rev().find(|ts| ts.first_gid <= gid).map(|ts| (ts, gid - ts.first_gid))
+    }
+}
+
+impl Tileset {
+    fn external(first_gid: u32, source: &str) -> Tileset {
+        Tileset {
+            first_gid: first_gid,
+            source: Some(source.to_string()),
+            name: String::new(),
+            image: None,
+            image_size: V2(0, 0),
+            tile_size: V2(0, 0),
+            margin: 0,
+            spacing: 0,
+            columns: 0,
+            tile_count: 0,
+            tile_images: HashMap::new(),
+            properties: HashMap::new(),
+        }
+    }
+
+    /// Parse an external tileset file in the Tiled JSON format.
+    pub fn parse(text: &str, first_gid: u32) -> Result<Tileset, String> {
+        let json = try!(Json::from_str(text).map_err(|e| format!("{:?}", e)));
+        Tileset::from_json(&json, first_gid)
+    }
+
+    fn from_json(json: &Json, first_gid: u32) -> Result<Tileset, String> {
+        let name = string(json, "name");
+        let tile_size = V2(try!(int(json, "tilewidth")), try!(int(json, "tileheight")));
+        let margin = opt_int(json, "margin");
+        let spacing = opt_int(json, "spacing");
+        let image = json.find("image").and_then(|x| x.as_string()).map(|x| x.to_string());
+
+        let mut image_size = V2(0, 0);
+        let mut columns = 0;
+        let mut tile_images = HashMap::new();
+        if image.is_some() {
+            image_size = V2(try!(int(json, "imagewidth")), try!(int(json, "imageheight")));
+            let step = tile_size + V2(spacing, spacing);
+            if step.0 <= 0 || step.1 <= 0 {
+                return Err(format!("Bad tile size {:?} with spacing {} in tileset {}", tile_size, spacing, name));
+            }
+            columns = match json.find("columns") {
+                Some(_) => try!(int(json, "columns")),
+                None => (image_size.0 - 2 * margin + spacing) / step.0,
+            };
+        } else {
+            // An image collection, the images are listed with the tiles.
+            let tiles = try!(array(json, "tiles").map_err(
+                |_| format!("Tileset {} has neither an image nor tile images", name)));
+            for tile in tiles.iter() {
+                tile_images.insert(try!(int(tile, "id")) as u32, TileImage {
+                    image: try!(str_field(tile, "image")),
+                    size: V2(try!(int(tile, "imagewidth")), try!(int(tile, "imageheight"))),
+                });
+            }
+        }
+        let tile_count = match json.find("tilecount") {
+            Some(_) => try!(int(json, "tilecount")),
+            None if image.is_none() => tile_images.len() as i32,
+            None => columns * ((image_size.1 - 2 * margin + spacing) / (tile_size.1 + spacing)),
+        };
+
+        Ok(Tileset {
+            first_gid: first_gid,
+            source: None,
+            name: name,
+            image: image,
+            image_size: image_size,
+            tile_size: tile_size,
+            margin: margin,
+            spacing: spacing,
+            columns: columns,
+            tile_count: tile_count as u32,
+            tile_images: tile_images,
+            properties: try!(properties(json)),
+        })
+    }
+
+    /// Area of a tile in the tileset image. For image collection tilesets
+    /// this is the whole image of the tile.
+    pub fn tile_rect(&self, idx: u32) -> Rect<i32> {
+        if self.columns <= 0 {
+            let size = self.tile_images.get(&idx).map_or(self.tile_size, |t| t.size);
+            return Rect(V2(0, 0), size);
+        }
+        let idx = idx as i32;
+        let step = self.tile_size + V2(self.spacing, self.spacing);
+        Rect(V2(self.margin + idx % self.columns * step.0, self.margin + idx / self.columns * step.1),
+             self.tile_size)
+    }
+
+    /// Add the tiles of the tileset to an atlas. Returns the atlas indices
+    /// of the tiles in tileset order. Panics for image collection tilesets,
+    /// which have no single image to cut the tiles from.
+    pub fn push_tiles<P: Pixel<Subpixel=u8> + 'static, I: GenericImage<Pixel=P>>(
+        &self, builder: &mut AtlasBuilder, image: &mut I) -> Vec<usize> {
+        assert!(self.image.is_some(), "Can't cut tiles from image collection tileset {}", self.name);
+        (0..self.tile_count).map(|i| {
+            let Rect(pos, dim) = self.tile_rect(i);
+            builder.push(V2(0, 0), &SubImage::new(
+                image, pos.0 as u32, pos.1 as u32, dim.0 as u32, dim.1 as u32))
+        }).collect()
+    }
+}
+
+fn object(json: &Json) -> Result<Object, String> {
+    let pos = V2(try!(int(json, "x")), try!(int(json, "y")));
+    let dim = V2(opt_int(json, "width"), opt_int(json, "height"));
+    Ok(Object {
+        id: opt_int(json, "id") as u32,
+        name: string(json, "name"),
+        // Tiled 1.9 renamed type to class.
+        kind: match json.find("type") {
+            Some(_) => string(json, "type"),
+            None => string(json, "class"),
+        },
+        bounds: Rect(pos, dim),
+        gid: json.find("gid").and_then(|x| x.as_u64()).map(|x| x as u32),
+        properties: try!(properties(json)),
+    })
+}
+
+/// Read the tile values of a tile layer.
+fn tile_data(layer: &Json, len: usize) -> Result<Vec<u32>, String> {
+    let mut ret = Vec::new();
+    match *try!(field(layer, "data")) {
+        Json::Array(ref a) => {
+            for x in a.iter() {
+                ret.push(try!(x.as_u64().ok_or("Bad tile value".to_string())) as u32);
+            }
+        }
+        Json::String(ref s) => {
+            match layer.find("encoding").and_then(|x| x.as_string()) {
+                Some("base64") => {
+                    if layer.find("compression").and_then(|x| x.as_string()).map_or(false, |x| x != "") {
+                        return Err("Compressed tile data is not supported".to_string());
+                    }
+                    let bytes = try!(s.trim().from_base64().map_err(|e| format!("{:?}", e)));
+                    for b in bytes.chunks(4) {
+                        if b.len() < 4 { return Err("Truncated tile data".to_string()); }
+                        ret.push(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24);
+                    }
+                }
+                _ => {
+                    for x in s.split(',') {
+                        ret.push(try!(x.trim().parse().map_err(|_| format!("Bad tile value {}", x))));
+                    }
+                }
+            }
+        }
+        _ => { return Err("Bad tile data".to_string()); }
+    }
+
+    if ret.len() != len {
+        return Err(format!("Expected {} tiles in layer, got {}", len, ret.len()));
+    }
+    Ok(ret)
+}
+
+/// Read custom properties in either the old object format or the array
+/// format of Tiled 1.2 and later. The values are turned into strings.
+fn properties(json: &Json) -> Result<HashMap<String, String>, String> {
+    let mut ret = HashMap::new();
+    match json.find("properties") {
+        None => {}
+        Some(&Json::Object(ref obj)) => {
+            for (k, v) in obj.iter() { ret.insert(k.clone(), value_string(v)); }
+        }
+        Some(&Json::Array(ref a)) => {
+            for p in a.iter() {
+                ret.insert(try!(str_field(p, "name")), value_string(try!(field(p, "value"))));
+            }
+        }
+        _ => { return Err("Bad properties".to_string()); }
+    }
+    Ok(ret)
+}
+
+fn value_string(value: &Json) -> String {
+    match *value {
+        Json::String(ref s) => s.clone(),
+        Json::Null => String::new(),
+        ref x => x.to_string(),
+    }
+}
+
+fn field<'a>(json: &'a Json, key: &str) -> Result<&'a Json, String> {
+    json.find(key).ok_or(format!("Missing field {}", key))
+}
+
+fn int(json: &Json, key: &str) -> Result<i32, String> {
+    try!(field(json, key)).as_f64().map(|x| x.round() as i32).ok_or(format!("Bad number field {}", key))
+}
+
+fn opt_int(json: &Json, key: &str) -> i32 {
+    int(json, key).unwrap_or(0)
+}
+
+fn str_field(json: &Json, key: &str) -> Result<String, String> {
+    try!(field(json, key)).as_string().map(|x| x.to_string()).ok_or(format!("Bad string field {}", key))
+}
+
+fn string(json: &Json, key: &str) -> String {
+    str_field(json, key).unwrap_or(String::new())
+}
+
+fn array<'a>(json: &'a Json, key: &str) -> Result<&'a [Json], String> {
+    try!(field(json, key)).as_array().map(|x| &x[..]).ok_or(format!("Bad array field {}", key))
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_parse() {
+        use super::{TiledMap, Tileset, gid};
+        use geom::{V2, Rect};
+
+        let map = TiledMap::parse(r#"{
+ "width": 2, "height": 2, "tilewidth": 16, "tileheight": 8,
+ "orientation": "orthogonal", "infinite": false,
+ "properties": [{"name": "title", "type": "string", "value": "Cave"}],
+ "tilesets": [
+  {"firstgid": 1, "name": "terrain", "image": "terrain.png",
+   "imagewidth": 52, "imageheight": 19, "tilewidth": 16, "tileheight": 8,
+   "margin": 1, "spacing": 1},
+  {"firstgid": 7, "source": "items.json"}],
+ "layers": [
+  {"type": "tilelayer", "name": "ground", "width": 2, "height": 2,
+   "data": [1, 2, 3, 2147483652]},
+  {"type": "group", "name": "upper", "layers": [
+   {"type": "tilelayer", "name": "items", "width": 2, "height": 2,
+    "encoding": "base64", "data": "AQAAAAIAAAAAAAAAAwAAAA=="},
+   {"type": "objectgroup", "name": "spawns", "objects": [
+    {"id": 3, "name": "boss", "type": "monster", "x": 16, "y": 8.2,
+     "width": 32, "height": 16, "properties": {"hp": 20, "angry": true}}]}]}]
+}"#).unwrap();
+
+        assert_eq!(V2(2, 2), map.tiles.size());
+        assert_eq!(V2(16, 8), map.tile_size);
+        assert_eq!(Some(&"Cave".to_string()), map.properties.get("title"));
+        assert_eq!(vec!["ground".to_string(), "items".to_string()], map.layer_names);
+        assert_eq!(2, map.tiles.num_layers());
+        assert_eq!(3, map.tiles.layer(0)[V2(0, 1)]);
+        assert_eq!(4, gid(map.tiles.layer(0)[V2(1, 1)]));
+        assert_eq!(2, map.tiles.layer(1)[V2(1, 0)]);
+        assert_eq!(0, map.tiles.layer(1)[V2(0, 1)]);
+
+        let ts = &map.tilesets[0];
+        assert_eq!(Some("terrain.png".to_string()), ts.image);
+        assert_eq!(3, ts.columns);
+        assert_eq!(6, ts.tile_count);
+        assert_eq!(Rect(V2(18, 10), V2(16, 8)), ts.tile_rect(4));
+        assert_eq!(Some("items.json".to_string()), map.tilesets[1].source);
+        assert_eq!(Some(3), map.tileset(4).map(|(ts, i)| { assert_eq!("terrain", &ts.name[..]); i }));
+        assert_eq!(Some(1), map.tileset(8).map(|(_, i)| i));
+
+        let obj = &map.object_layers[0].objects[0];
+        assert_eq!("monster", &obj.kind[..]);
+        assert_eq!(Rect(V2(16, 8), V2(32, 16)), obj.bounds);
+        assert_eq!(Some(&"20".to_string()), obj.properties.get("hp"));
+        assert_eq!(Some(&"true".to_string()), obj.properties.get("angry"));
+
+        assert!(TiledMap::parse("{}").is_err());
+        assert!(TiledMap::parse(r#"{"width": 1, "height": 1, "tilewidth": 1, "tileheight": 1,
+            "tilesets": [], "layers": [{"type": "tilelayer", "data": [1, 2]}]}"#).is_err());
+        // Tiles can't be counted without a size.
+        assert!(Tileset::parse(r#"{"name": "flat", "image": "flat.png", "imagewidth": 16,
+            "imageheight": 16, "tilewidth": 0, "tileheight": 8}"#, 1).is_err());
+    }
+
+    #[test]
+    fn test_image_collection() {
+        use super::{Tileset, TileImage};
+        use geom::{V2, Rect};
+
+        let ts = Tileset::parse(r#"{
+ "name": "props", "columns": 0, "tilewidth": 32, "tileheight": 48,
+ "tilecount": 2, "margin": 0, "spacing": 0,
+ "tiles": [
+  {"id": 0, "image": "barrel.png", "imagewidth": 16, "imageheight": 24},
+  {"id": 3, "image": "tree.png", "imagewidth": 32, "imageheight": 48}]
+}"#, 10).unwrap();
+
+        assert_eq!(None, ts.image);
+        assert_eq!(0, ts.columns);
+        assert_eq!(2, ts.tile_count);
+        assert_eq!(Some(&TileImage { image: "tree.png".to_string(), size: V2(32, 48) }),
+                   ts.tile_images.get(&3));
+        assert_eq!(Rect(V2(0, 0), V2(16, 24)), ts.tile_rect(0));
+        assert_eq!(Rect(V2(0, 0), V2(32, 48)), ts.tile_rect(1));
+
+        let err = Tileset::parse(r#"{"name": "broken", "tilewidth": 8, "tileheight": 8}"#, 1).unwrap_err();
+        assert!(err.contains("broken"));
+    }
+}
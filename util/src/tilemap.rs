@@ -0,0 +1,204 @@
+use std::cmp::{min, max};
+use std::ops::{Index, IndexMut};
+use geom::{V2, Rect, RectIter};
+use text::Map2DUtil;
+
+/// A single dense grid of tiles in a tile map.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Layer<T> {
+    size: V2<i32>,
+    tiles: Vec<T>,
+}
+
+impl<T: Clone> Layer<T> {
+    pub fn new(size: V2<i32>, fill: T) -> Layer<T> {
+        assert!(size.0 >= 0 && size.1 >= 0);
+        Layer {
+            size: size,
+            tiles: (0..(size.0 * size.1)).map(|_| fill.clone()).collect(),
+        }
+    }
+}
+
+impl<T> Layer<T> {
+    pub fn size(&self) -> V2<i32> { self.size }
+
+    /// The rectangle of valid tile positions.
+    pub fn bounds(&self) -> Rect<i32> { Rect(V2(0, 0), self.size) }
+
+    pub fn contains(&self, pos: V2<i32>) -> bool { self.bounds().contains(&pos) }
+
+    fn offset(&self, pos: V2<i32>) -> Option<usize> {
+        if self.contains(pos) { Some((pos.0 + pos.1 * self.size.0) as usize) } else { None }
+    }
+
+    /// Return the tile at pos, or None if pos is outside the layer.
+    pub fn get(&self, pos: V2<i32>) -> Option<&T> {
+        self.offset(pos).map(|i| &self.tiles[i])
+    }
+
+    pub fn get_mut(&mut self, pos: V2<i32>) -> Option<&mut T> {
+        match self.offset(pos) {
+            Some(i) => Some(&mut self.tiles[i]),
+            None => None
+        }
+    }
+
+    /// Set the tile at pos. Return false if pos is outside the layer.
+    pub fn set(&mut self, pos: V2<i32>, tile: T) -> bool {
+        match self.offset(pos) {
+            Some(i) => { self.tiles[i] = tile; true }
+            None => false
+        }
+    }
+
+    /// Iterate the positions and tiles of an area of the layer. The parts of
+    /// the area outside the layer are skipped.
+    pub fn region<'a>(&'a self, area: &Rect<i32>) -> Region<'a, T> {
+        let (mn, mx) = (area.mn(), area.mx());
+        let mn = V2(max(mn.0, 0), max(mn.1, 0));
+        let mx = V2(min(mx.0, self.size.0), min(mx.1, self.size.1));
+        // An empty area yields no positions.
+        let clip = if mx.0 > mn.0 && mx.1 > mn.1 { Rect(mn, mx - mn) } else { Rect(V2(0, 0), V2(0, 0)) };
+        Region { layer: self, iter: clip.iter() }
+    }
+
+    /// Iterate the positions and tiles of the whole layer.
+    pub fn iter<'a>(&'a self) -> Region<'a, T> {
+        self.region(&self.bounds())
+    }
+}
+
+/// Index a layer by position, panicking if the position is out of bounds.
+impl<T> Index<V2<i32>> for Layer<T> {
+    type Output = T;
+    fn index(&self, pos: V2<i32>) -> &T {
+        match self.get(pos) {
+            Some(t) => t,
+            None => panic!("Tile position {:?} out of bounds {:?}", pos, self.size)
+        }
+    }
+}
+
+impl<T> IndexMut<V2<i32>> for Layer<T> {
+    fn index_mut(&mut self, pos: V2<i32>) -> &mut T {
+        let size = self.size;
+        match self.get_mut(pos) {
+            Some(t) => t,
+            None => panic!("Tile position {:?} out of bounds {:?}", pos, size)
+        }
+    }
+}
+
+/// Iterator over an area of a tile map layer.
+pub struct Region<'a, T: 'a> {
+    layer: &'a Layer<T>,
+    iter: RectIter<i32>,
+}
+
+impl<'a, T> Iterator for Region<'a, T> {
+    type Item = (V2<i32>, &'a T);
+
+    fn next(&mut self) -> Option<(V2<i32>, &'a T)> {
+        match self.iter.next() {
+            Some(pos) => self.layer.get(pos).map(|t| (pos, t)),
+            None => None
+        }
+    }
+}
+
+/// A rectangular tile map made of equally sized layers. Layer 0 is the
+/// bottom one.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TileMap<T> {
+    size: V2<i32>,
+    layers: Vec<Layer<T>>,
+}
+
+impl<T: Clone> TileMap<T> {
+    /// Create a tile map with one layer filled with the fill tile.
+    pub fn new(size: V2<i32>, fill: T) -> TileMap<T> {
+        TileMap {
+            size: size,
+            layers: vec![Layer::new(size, fill)],
+        }
+    }
+
+    /// Add a new top layer filled with the fill tile and return its index.
+    pub fn add_layer(&mut self, fill: T) -> usize {
+        self.layers.push(Layer::new(self.size, fill));
+        self.layers.len() - 1
+    }
+
+    /// Build a single layer map from a text drawing of it. The map is
+    /// sized to fit the text, the tile for each char is given by f and
+    /// positions not covered by the text get the fill tile.
+    pub fn from_text<F: Fn(char) -> T>(text: &str, fill: T, f: F) -> TileMap<T> {
+        let mut size = V2(0, 0);
+        for (_, x, y) in text.chars().map2d() {
+            size = V2(max(size.0, x + 1), max(size.1, y + 1));
+        }
+        let mut ret = TileMap::new(size, fill);
+        for (c, x, y) in text.chars().map2d() {
+            ret.layers[0].set(V2(x, y), f(c));
+        }
+        ret
+    }
+}
+
+impl<T> TileMap<T> {
+    pub fn size(&self) -> V2<i32> { self.size }
+
+    /// The rectangle of valid tile positions.
+    pub fn bounds(&self) -> Rect<i32> { Rect(V2(0, 0), self.size) }
+
+    pub fn contains(&self, pos: V2<i32>) -> bool { self.bounds().contains(&pos) }
+
+    pub fn num_layers(&self) -> usize { self.layers.len() }
+
+    pub fn layer<'a>(&'a self, idx: usize) -> &'a Layer<T> { &self.layers[idx] }
+
+    pub fn layer_mut<'a>(&'a mut self, idx: usize) -> &'a mut Layer<T> { &mut self.layers[idx] }
+
+    pub fn layers<'a>(&'a self) -> &'a [Layer<T>] { &self.layers[..] }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_tilemap() {
+        use super::TileMap;
+        use geom::{V2, Rect};
+
+        let mut map = TileMap::from_text("#.#\n..\n", ' ', |c| c);
+        assert_eq!(V2(3, 2), map.size());
+        assert_eq!('#', map.layer(0)[V2(2, 0)]);
+        assert_eq!(Some(&'.'), map.layer(0).get(V2(1, 1)));
+        // Filled in past the end of the short line.
+        assert_eq!(Some(&' '), map.layer(0).get(V2(2, 1)));
+        assert_eq!(None, map.layer(0).get(V2(3, 0)));
+        assert_eq!(None, map.layer(0).get(V2(-1, 0)));
+
+        let top = map.add_layer('x');
+        assert_eq!(2, map.num_layers());
+        assert!(map.layer_mut(top).set(V2(0, 1), 'y'));
+        assert!(!map.layer_mut(top).set(V2(0, 2), 'y'));
+        map.layer_mut(top)[V2(1, 1)] = 'z';
+
+        let tiles: Vec<(V2<i32>, char)> = map.layer(top).region(&Rect(V2(-1, 0), V2(3, 5)))
+            .map(|(p, &t)| (p, t)).collect();
+        assert_eq!(vec![(V2(0, 0), 'x'), (V2(1, 0), 'x'), (V2(0, 1), 'y'), (V2(1, 1), 'z')], tiles);
+        assert_eq!(0, map.layer(0).region(&Rect(V2(5, 5), V2(2, 2))).count());
+        assert_eq!(6, map.layer(0).iter().count());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_out_of_bounds() {
+        use super::TileMap;
+        use geom::V2;
+
+        let map = TileMap::new(V2(2, 2), 0u8);
+        map.layer(0)[V2(2, 0)];
+    }
+}
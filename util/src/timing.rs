@@ -1,6 +1,7 @@
 use time;
 use std::time::duration::Duration;
 use std::thread;
+use std::f64::consts::PI;
 
 pub fn cycle_anim<'a, T>(period_s: f64, frames: &'a [T]) -> &'a T {
     assert!(period_s > 0.0);
@@ -25,6 +26,13 @@ pub fn single_anim<'a, T>(start_s: f64, period_s: f64, frames: &'a [T]) -> &'a T
     &frames[idx as usize]
 }
 
+/// Time-plot of a sine wave between -1 and 1 with the given period. Phase is
+/// given in fractions of the period.
+pub fn wave(period_s: f64, phase: f64) -> f64 {
+    assert!(period_s > 0.0);
+    ((time::precise_time_s() / period_s + phase) * 2.0 * PI).sin()
+}
+
 #[derive(Copy)]
 pub struct Ticker {
     period_s: f64,
@@ -98,3 +106,94 @@ impl TimePerFrame {
             (1.0 - self.update_weight) * self.average;
     }
 }
+
+/// Progressive reveal of text for dialogue boxes.
+#[derive(Copy, Clone, Debug)]
+pub struct Typewriter {
+    start_t: f64,
+    skipped: bool,
+    /// Number of characters revealed per second.
+    pub chars_per_second: f64,
+    /// Extra delay after sentence-ending punctuation.
+    pub sentence_pause_s: f64,
+    /// Extra delay after commas and other clause-separating punctuation.
+    pub clause_pause_s: f64,
+}
+
+impl Typewriter {
+    /// Create a new typewriter that starts revealing text right away.
+    pub fn new(chars_per_second: f64) -> Typewriter {
+        assert!(chars_per_second > 0.0);
+        Typewriter {
+            start_t: time::precise_time_s(),
+            skipped: false,
+            chars_per_second: chars_per_second,
+            sentence_pause_s: 0.4,
+            clause_pause_s: 0.15,
+        }
+    }
+
+    /// Start revealing from the beginning again, eg. for a new page of text.
+    pub fn restart(&mut self) {
+        self.start_t = time::precise_time_s();
+        self.skipped = false;
+    }
+
+    /// Reveal the whole text immediately.
+    pub fn skip(&mut self) {
+        self.skipped = true;
+    }
+
+    /// Return the number of chars from the start of the text that are
+    /// visible at the current time.
+    pub fn visible<I: Iterator<Item=char>>(&self, chars: I) -> usize {
+        self.visible_at(time::precise_time_s() - self.start_t, chars)
+    }
+
+    /// Return whether the whole text has been revealed.
+    pub fn is_finished<I: Iterator<Item=char>+Clone>(&self, chars: I) -> bool {
+        self.visible(chars.clone()) == chars.count()
+    }
+
+    /// Return the number of chars visible after elapsed_s seconds.
+    pub fn visible_at<I: Iterator<Item=char>>(&self, elapsed_s: f64, chars: I) -> usize {
+        let mut t = 0.0;
+        let mut n = 0;
+        for c in chars {
+            t += 1.0 / self.chars_per_second;
+            if !self.skipped && t > elapsed_s { break; }
+            n += 1;
+            t += match c {
+                '.' | '!' | '?' => self.sentence_pause_s,
+                ',' | ';' | ':' => self.clause_pause_s,
+                _ => 0.0
+            };
+        }
+        n
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_typewriter() {
+        use super::Typewriter;
+
+        let mut tw = Typewriter::new(10.0);
+        tw.sentence_pause_s = 1.0;
+        tw.clause_pause_s = 0.5;
+
+        assert_eq!(0, tw.visible_at(0.0, "abc".chars()));
+        assert_eq!(1, tw.visible_at(0.15, "abc".chars()));
+        assert_eq!(3, tw.visible_at(10.0, "abc".chars()));
+        assert_eq!(2, tw.visible_at(1.0, "a.b".chars()));
+        assert_eq!(3, tw.visible_at(1.35, "a.b".chars()));
+        assert_eq!(2, tw.visible_at(0.6, "a,b".chars()));
+        assert_eq!(3, tw.visible_at(0.85, "a,b".chars()));
+        assert!(!tw.is_finished("lorem ipsum dolor sit amet".chars()));
+
+        tw.skip();
+        assert_eq!(3, tw.visible_at(0.0, "abc".chars()));
+        assert!(tw.is_finished("lorem ipsum dolor sit amet".chars()));
+    }
+}
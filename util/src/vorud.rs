@@ -134,8 +134,8 @@ impl FromVorud<()> for Vec<u8> {
         let mut ret = Vec::new();
         for chunk in (&s[..]).split('-') {
             let x = try!(durov_chunk(chunk));
-            ret.push((x / 0xff) as u8);
-            ret.push((x % 0xff) as u8);
+            ret.push((x >> 8) as u8);
+            ret.push((x & 0xff) as u8);
         }
         Ok(ret)
     }
@@ -144,10 +144,10 @@ impl FromVorud<()> for Vec<u8> {
 impl ToVorud for u32 {
     fn to_vorud(&self) -> Vorud {
         let mut vec = Vec::new();
-        vec.push(((*self >> 24) % 0xff) as u8);
-        vec.push(((*self >> 16) % 0xff) as u8);
-        vec.push(((*self >> 8) % 0xff) as u8);
-        vec.push((*self % 0xff) as u8);
+        vec.push(((*self >> 24) & 0xff) as u8);
+        vec.push(((*self >> 16) & 0xff) as u8);
+        vec.push(((*self >> 8) & 0xff) as u8);
+        vec.push((*self & 0xff) as u8);
         (&vec[..]).to_vorud()
     }
 }
@@ -177,5 +177,7 @@ mod test {
         assert_eq!(super::Vorud("babab-babab".to_string()), 0u32.to_vorud());
         assert_eq!(super::Vorud("babab-babad".to_string()), 1u32.to_vorud());
         assert_eq!(Ok(1u32), super::FromVorud::from_vorud(&super::Vorud("babab-babad".to_string())));
+        assert_eq!(Ok(0xffffffffu32), super::FromVorud::from_vorud(&0xffffffffu32.to_vorud()));
+        assert_eq!(Ok(0x12ff00feu32), super::FromVorud::from_vorud(&0x12ff00feu32.to_vorud()));
     }
 }